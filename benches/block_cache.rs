@@ -0,0 +1,111 @@
+//! Time the block cache against the reference interpreter on the test ROMs.
+//!
+//! Run with `cargo bench --bench block_cache`. The functional test is included
+//! when `tests/roms/6502_functional_test.bin` has been fetched.
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use nes::{
+    cpu::{HaltReason, Variant, CPU6502},
+    mem::Memory,
+};
+
+const MAX_CYCLES: u64 = 200_000_000;
+
+/// Timed runs of each engine; the fastest is reported.
+const RUNS: usize = 5;
+
+struct Rom {
+    name: &'static str,
+    offset: u16,
+    start: u16,
+    variant: Variant,
+}
+
+const ROMS: [Rom; 3] = [
+    Rom {
+        name: "6502_functional_test.bin",
+        offset: 0x0000,
+        start: 0x0400,
+        variant: Variant::NMOS6502,
+    },
+    Rom {
+        name: "6502_decimal_test.bin",
+        offset: 0x0200,
+        start: 0x0200,
+        variant: Variant::NMOS6502,
+    },
+    Rom {
+        name: "65c02_decimal_test.bin",
+        offset: 0x0200,
+        start: 0x0200,
+        variant: Variant::WDC65C02,
+    },
+];
+
+/// Run `rom` to its trap, returning the time taken and the cycles run.
+fn run(rom: &Rom, data: &[u8], block_cache: bool) -> (Duration, u64) {
+    let mut mem = Memory::new();
+    mem.load(data, rom.offset);
+
+    let mut cpu = CPU6502::new(mem);
+    cpu.variant = rom.variant;
+    cpu.block_cache = block_cache;
+    cpu.reset();
+    cpu.pc = rom.start;
+
+    let start = Instant::now();
+    let reason = cpu.run_until(|cpu| match cpu.halt_reason() {
+        None if cpu.cycles >= MAX_CYCLES => Some(HaltReason::CycleLimit(cpu.cycles)),
+        reason => reason,
+    });
+    let elapsed = start.elapsed();
+
+    assert!(
+        matches!(reason, HaltReason::InfiniteLoop(_)),
+        "{}: {}",
+        rom.name,
+        reason
+    );
+    (elapsed, cpu.cycles)
+}
+
+fn fastest(rom: &Rom, data: &[u8], block_cache: bool) -> (Duration, u64) {
+    (0..RUNS)
+        .map(|_| run(rom, data, block_cache))
+        .min_by_key(|(elapsed, _)| *elapsed)
+        .unwrap()
+}
+
+fn main() {
+    let roms = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("roms");
+
+    for rom in &ROMS {
+        let data = match fs::read(roms.join(rom.name)) {
+            Ok(data) => data,
+            Err(_) => {
+                println!("{:<26} not found, see tests/roms/README.md", rom.name);
+                continue;
+            }
+        };
+
+        let (reference, cycles) = fastest(rom, &data, false);
+        let (cached, cached_cycles) = fastest(rom, &data, true);
+        assert_eq!(cached_cycles, cycles, "{}: cycle counts differ", rom.name);
+
+        let mhz = |elapsed: Duration| cycles as f64 / elapsed.as_secs_f64() / 1e6;
+        println!(
+            "{:<26} reference {:>8.1} MHz, block cache {:>8.1} MHz, {:.2}x",
+            rom.name,
+            mhz(reference),
+            mhz(cached),
+            reference.as_secs_f64() / cached.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::{convert::TryFrom, io};
+
+use crate::{
+    device::Device,
+    io::IO,
+    machine::DEFAULT_CPU_CLOCK,
+    serial::{
+        backend::{Backend, NullBackend},
+        Format, Parity,
+    },
+    state::{SaveState, StateReader, StateWriter},
+};
+
+const ACIA_CONTROL: u16 = 0;
+const ACIA_STATUS: u16 = 0;
+const ACIA_DATA: u16 = 1;
+
+/// Transmit and receive clock, as on Grant Searle's SBC
+const DEFAULT_ACIA_CLOCK: u64 = 1_843_200;
+
+bitflags! {
+    pub struct Status: u8 {
+        const RX_FULL = 1 << 0;
+        const TX_EMPTY = 1 << 1;
+        /// Carrier lost
+        const DCD = 1 << 2;
+        /// Clear to send, active low
+        const CTS = 1 << 3;
+        const FRAMING_ERROR = 1 << 4;
+        const OVERRUN = 1 << 5;
+        const PARITY_ERROR = 1 << 6;
+        const IRQ = 1 << 7;
+    }
+}
+
+bitflags! {
+    pub struct Control: u8 {
+        /// Clock divide select. Both bits set is a master reset.
+        const DIVIDE = 0b11;
+        const WORD_SELECT = 0b111 << 2;
+        /// Transmitter interrupt, RTS and break control
+        const TX_CONTROL = 0b11 << 5;
+        const RX_IRQ_ENABLE = 1 << 7;
+    }
+}
+
+/// Transmitter control field values
+const TX_IRQ: u8 = 0b01 << 5;
+const TX_RTS_HIGH: u8 = 0b10 << 5;
+const TX_BREAK: u8 = 0b11 << 5;
+
+/// Motorola 6850 ACIA
+///
+/// Only the RS line (address bit 0) is decoded, so it can be mapped anywhere
+/// and mirrored across a larger range, like on Grant Searle's SBC at $A000.
+///
+/// - $0: Control (write) / Status (read)
+/// - $1: Transmit / receive data
+pub struct Acia6850 {
+    backend: Box<dyn Backend>,
+    cpu_clock: u64,
+    clock: u64,
+    status: Status,
+    control: Control,
+    /// Receive data register
+    rx_data: u8,
+    /// Transmit data register, waiting for the shift register
+    tx_data: Option<u8>,
+    /// Character being shifted out
+    tx_shift: Option<u8>,
+    /// Cycles until the transmitter finishes the current character
+    tx_cycles: u64,
+    /// Cycles until the receiver can take another character
+    rx_cycles: u64,
+}
+
+impl Acia6850 {
+    pub fn new(backend: Box<dyn Backend>) -> Self {
+        Self {
+            backend,
+            cpu_clock: DEFAULT_CPU_CLOCK,
+            clock: DEFAULT_ACIA_CLOCK,
+            status: Status::TX_EMPTY,
+            control: Control::empty(),
+            rx_data: 0,
+            tx_data: None,
+            tx_shift: None,
+            tx_cycles: 0,
+            rx_cycles: 0,
+        }
+    }
+
+    /// An ACIA with nothing attached. Output is dropped and no input ever arrives.
+    pub fn disconnected() -> Self {
+        Self::new(Box::new(NullBackend))
+    }
+
+    /// Set the transmit and receive clock in Hz, before division.
+    /// Panics if `hz` is 0.
+    pub fn with_clock(mut self, hz: u64) -> Self {
+        assert!(hz > 0, "the ACIA clock can't be 0 Hz");
+        self.clock = hz;
+        self
+    }
+
+    fn format_(&self) -> Format {
+        let (bits, parity, stop_bits) = match (self.control & Control::WORD_SELECT).bits() >> 2 {
+            0b000 => (7, Some(Parity::Even), 2),
+            0b001 => (7, Some(Parity::Odd), 2),
+            0b010 => (7, Some(Parity::Even), 1),
+            0b011 => (7, Some(Parity::Odd), 1),
+            0b100 => (8, None, 2),
+            0b101 => (8, None, 1),
+            0b110 => (8, Some(Parity::Even), 1),
+            _ => (8, Some(Parity::Odd), 1),
+        };
+        Format {
+            bits,
+            parity,
+            stop_bits,
+        }
+    }
+
+    fn tx_control_(&self) -> u8 {
+        (self.control & Control::TX_CONTROL).bits()
+    }
+
+    fn divide_(&self) -> u64 {
+        match (self.control & Control::DIVIDE).bits() {
+            0b00 => 1,
+            0b01 => 16,
+            _ => 64,
+        }
+    }
+
+    /// CPU cycles per character, start and stop bits included.
+    fn char_cycles_(&self) -> u64 {
+        let bits = self.format_().frame_bits() as u128;
+        let cycles = self.cpu_clock as u128 * bits * self.divide_() as u128 / self.clock as u128;
+        u64::try_from(cycles).unwrap_or(u64::MAX).max(1)
+    }
+
+    fn master_reset_(&mut self) {
+        self.status = Status::TX_EMPTY;
+        self.tx_data = None;
+        self.tx_shift = None;
+        self.tx_cycles = 0;
+        self.rx_cycles = 0;
+    }
+
+    fn send_(&mut self, data: u8) {
+        let byte = self.format_().encode(data);
+        self.backend.write(byte);
+    }
+
+    /// Move the transmitter and receiver on by `cycles`.
+    fn clock_(&mut self, mut cycles: u64) {
+        // Transmitter
+        let mut tx_left = cycles;
+        loop {
+            if self.tx_shift.is_none() {
+                match self.tx_data.take() {
+                    Some(data) => {
+                        self.tx_shift = Some(data);
+                        self.tx_cycles = self.char_cycles_();
+                        self.status.insert(Status::TX_EMPTY);
+                    }
+                    None => break,
+                }
+            }
+
+            if self.tx_cycles > tx_left {
+                self.tx_cycles -= tx_left;
+                break;
+            }
+            tx_left -= self.tx_cycles;
+            self.tx_cycles = 0;
+            if let Some(data) = self.tx_shift.take() {
+                self.send_(data);
+            }
+        }
+
+        // Receiver. The backend is polled once per character time.
+        while cycles >= self.rx_cycles {
+            cycles -= self.rx_cycles;
+            self.rx_cycles = self.char_cycles_();
+            let dcd = self.backend.dcd();
+            self.status.set(Status::DCD, !dcd);
+
+            let byte = match self.backend.read() {
+                Some(byte) => byte,
+                None => break,
+            };
+
+            if self.status.contains(Status::RX_FULL) {
+                // The new character is lost
+                self.status.insert(Status::OVERRUN);
+            } else {
+                let (data, parity_error, framing_error) = self.format_().decode(byte);
+                self.rx_data = data;
+                self.status.insert(Status::RX_FULL);
+                self.status.set(Status::PARITY_ERROR, parity_error);
+                self.status.set(Status::FRAMING_ERROR, framing_error);
+            }
+        }
+        self.rx_cycles -= cycles.min(self.rx_cycles);
+    }
+
+    fn set_control_(&mut self, control: Control) {
+        if control.contains(Control::DIVIDE) {
+            self.master_reset_();
+            // The rest of the register is written as usual
+            self.control = (self.control & Control::DIVIDE) | (control - Control::DIVIDE);
+        } else {
+            self.control = control;
+        }
+
+        let tx_control = self.tx_control_();
+        let baud = self.clock / self.divide_();
+        self.backend.set_baud_rate(u32::try_from(baud).unwrap_or(u32::MAX));
+        self.backend.set_rts(tx_control != TX_RTS_HIGH);
+        self.backend.set_break(tx_control == TX_BREAK);
+    }
+
+    /// Status register with the IRQ bit worked out.
+    fn status_(&self) -> Status {
+        let rx_irq = self.control.contains(Control::RX_IRQ_ENABLE)
+            && self.status.intersects(Status::RX_FULL | Status::OVERRUN);
+        let tx_irq = self.tx_control_() == TX_IRQ && self.status.contains(Status::TX_EMPTY);
+
+        let mut status = self.status;
+        status.set(Status::IRQ, rx_irq || tx_irq);
+        status
+    }
+}
+
+impl IO for Acia6850 {
+    fn peek(&self, addr: u16) -> u8 {
+        match addr & 1 {
+            ACIA_STATUS => self.status_().bits(),
+            _ => self.rx_data,
+        }
+    }
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.peek(addr);
+        if addr & 1 == ACIA_DATA {
+            self.status.remove(Status::RX_FULL | Status::OVERRUN);
+        }
+        value
+    }
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr & 1 {
+            ACIA_CONTROL => self.set_control_(Control::from_bits_truncate(data)),
+            _ => {
+                // A character not yet picked up by the shift register is replaced
+                self.tx_data = Some(data);
+                self.status.remove(Status::TX_EMPTY);
+            }
+        }
+    }
+    fn tick(&mut self, cycles: u32) {
+        self.clock_(cycles as u64);
+    }
+    fn irq(&self) -> bool {
+        self.status_().contains(Status::IRQ)
+    }
+}
+
+impl Device for Acia6850 {
+    fn name(&self) -> &str {
+        "acia6850"
+    }
+
+    fn set_cpu_clock(&mut self, hz: u64) {
+        assert!(hz > 0, "the CPU clock can't be 0 Hz");
+        self.cpu_clock = hz;
+    }
+}
+
+/// Only the registers are saved. The backend belongs to the host.
+impl SaveState for Acia6850 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.status.bits());
+        w.u8(self.control.bits());
+        w.u8(self.rx_data);
+        w.bool(self.tx_data.is_some());
+        w.u8(self.tx_data.unwrap_or_default());
+        w.bool(self.tx_shift.is_some());
+        w.u8(self.tx_shift.unwrap_or_default());
+        w.u64(self.tx_cycles);
+        w.u64(self.rx_cycles);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.status = Status::from_bits_truncate(r.u8()?);
+        self.control = Control::from_bits_truncate(r.u8()?);
+        self.rx_data = r.u8()?;
+        let tx_data = (r.bool()?, r.u8()?);
+        self.tx_data = if tx_data.0 { Some(tx_data.1) } else { None };
+        let tx_shift = (r.bool()?, r.u8()?);
+        self.tx_shift = if tx_shift.0 { Some(tx_shift.1) } else { None };
+        self.tx_cycles = r.u64()?;
+        self.rx_cycles = r.u64()?;
+        Ok(())
+    }
+}
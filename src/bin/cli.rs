@@ -1,12 +1,33 @@
 use std::{
     borrow::BorrowMut,
     fs,
+    io::{self, stdout, BufWriter},
     path::{Path, PathBuf},
-    time::SystemTime,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
 };
 
-use clap::Parser;
-use nes::{debugger::Debugger, display::Display, tui::Tui};
+use clap::{Parser, ValueEnum};
+use nes::{
+    acia6850::Acia6850,
+    cpu::Variant,
+    debugger::{Debugger, RunState},
+    device::Mapping,
+    display::Display,
+    machine::{Machine, DEFAULT_CPU_CLOCK},
+    serial::{
+        backend::{
+            Backend, FileBackend, NullBackend, PtyBackend, StdioBackend, TcpBackend, TtyBackend,
+        },
+        script::{Outcome, Script, ScriptBackend, ScriptStatus},
+    },
+    tui::Tui,
+};
 
 /// 6502 CPU Emulator and Debugger
 #[derive(Parser, Debug)]
@@ -23,17 +44,197 @@ struct Args {
     /// Serial port device
     #[arg(long, short)]
     port: Option<PathBuf>,
+    /// Serial backend when there is no --port: pty, tcp:[HOST:]PORT, stdio,
+    /// file:INPUT (output goes to stdout) or none
+    #[arg(long, default_value = "pty")]
+    serial: String,
+    /// Drive the serial port from an expect-style script, running headless
+    /// until it finishes. Exits non-zero if the script fails.
+    #[arg(long, conflicts_with_all = ["port", "serial"])]
+    script: Option<PathBuf>,
     /// Run at the maximum possible speed
     #[arg(long, short)]
     maxspeed: bool,
     /// Start address (PC)
-    #[arg(long, short)]
-    start: Option<String>,
+    #[arg(long, short, value_parser = parse_address)]
+    start: Option<u16>,
+    /// Address to load the file at
+    #[arg(long, short, value_parser = parse_address, default_value = "0")]
+    load_address: u16,
+    /// Serial chip: 6551 (at $5000) or 6850 (at $A000)
+    #[arg(long, value_enum, default_value_t = Acia::Mos6551)]
+    acia: Acia,
+    /// Map the serial chip here instead
+    #[arg(long, value_parser = parse_address)]
+    acia_address: Option<u16>,
+    /// CPU variant
+    #[arg(long, short, value_enum, ignore_case = true, default_value_t = Cpu::Nmos6502)]
+    cpu: Cpu,
+    /// CPU clock in Hz, for pacing the run and timing the serial port
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        default_value_t = DEFAULT_CPU_CLOCK
+    )]
+    clock: u64,
+    /// Perform one bus access per clock cycle
+    #[arg(long)]
+    cycle_accurate: bool,
+    /// Run from a cache of decoded code (faster, for long headless runs)
+    #[arg(long)]
+    block_cache: bool,
+    /// Let BRK run its interrupt handler instead of halting
+    #[arg(long)]
+    no_halt_on_brk: bool,
+    /// Keep running in a branch or jump to itself with interrupts masked,
+    /// instead of halting
+    #[arg(long)]
+    no_halt_on_loop: bool,
+    /// Write a nestest-style trace of every instruction to a file ("-" for stdout)
+    #[arg(long)]
+    trace: Option<PathBuf>,
+    /// Restore a saved machine state before running
+    #[arg(long)]
+    load_state: Option<PathBuf>,
+    /// Save the machine state here when --run halts (also used by the TUI's F5/F9)
+    #[arg(long)]
+    save_state: Option<PathBuf>,
+}
+
+/// CPU variants the CLI can run
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Cpu {
+    #[value(name = "6502")]
+    Nmos6502,
+    #[value(name = "65c02")]
+    Wdc65c02,
+}
+
+impl From<Cpu> for Variant {
+    fn from(cpu: Cpu) -> Self {
+        match cpu {
+            Cpu::Nmos6502 => Variant::NMOS6502,
+            Cpu::Wdc65c02 => Variant::WDC65C02,
+        }
+    }
+}
+
+/// Serial chips the CLI can map
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Acia {
+    #[value(name = "6551")]
+    Mos6551,
+    #[value(name = "6850")]
+    Mc6850,
+}
+
+impl Acia {
+    /// Number of registers
+    fn size(self) -> u16 {
+        match self {
+            Acia::Mos6551 => 4,
+            Acia::Mc6850 => 2,
+        }
+    }
+}
+
+/// Host end of the serial backend chosen on the command line
+struct SerialConnection {
+    /// Where to connect to it, for the user
+    description: Option<String>,
+    /// Set when the host end hangs up
+    hung_up: Option<Arc<AtomicBool>>,
+    script: Option<ScriptStatus>,
+}
+
+impl SerialConnection {
+    /// Whether the host end is done with the machine
+    fn finished(&self) -> bool {
+        let hung_up = match &self.hung_up {
+            Some(hung_up) => hung_up.load(Ordering::SeqCst),
+            None => false,
+        };
+        let script_done = match &self.script {
+            Some(script) => script.outcome() != Outcome::Running,
+            None => false,
+        };
+        hung_up || script_done
+    }
+}
+
+fn open_serial(args: &Args) -> io::Result<(Box<dyn Backend>, SerialConnection)> {
+    let connection = |backend: Box<dyn Backend>, description: Option<String>| {
+        let connection = SerialConnection {
+            description,
+            hung_up: None,
+            script: None,
+        };
+        (backend, connection)
+    };
+
+    if let Some(path) = &args.script {
+        let script = Script::parse(&fs::read_to_string(path)?)?;
+        let backend = ScriptBackend::new(script, stdout());
+        let connection = SerialConnection {
+            description: None,
+            hung_up: None,
+            script: Some(backend.status()),
+        };
+        return Ok((Box::new(backend), connection));
+    }
+
+    if let Some(path) = &args.port {
+        let backend = TtyBackend::open(&path.to_string_lossy())?;
+        return Ok(connection(Box::new(backend), None));
+    }
+
+    let serial = args.serial.as_str();
+    if let Some(addr) = serial.strip_prefix("tcp:") {
+        let backend = if addr.contains(':') {
+            TcpBackend::bind(addr)?
+        } else {
+            TcpBackend::bind(format!("127.0.0.1:{}", addr))?
+        };
+        let description = format!("Serial port on tcp://{}", backend.local_addr()?);
+        return Ok(connection(Box::new(backend), Some(description)));
+    }
+    if let Some(path) = serial.strip_prefix("file:") {
+        let backend = FileBackend::new(fs::read(path)?, stdout());
+        return Ok(connection(Box::new(backend), None));
+    }
+
+    match serial {
+        "pty" => {
+            let backend = PtyBackend::open()?;
+            let description = format!("Serial port on {}", backend.path());
+            Ok(connection(Box::new(backend), Some(description)))
+        }
+        "stdio" => {
+            let backend = StdioBackend::open()?;
+            let connection = SerialConnection {
+                description: None,
+                hung_up: Some(backend.hung_up()),
+                script: None,
+            };
+            Ok((Box::new(backend), connection))
+        }
+        "none" => Ok(connection(Box::new(NullBackend), None)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown serial backend {:?}", serial),
+        )),
+    }
+}
+
+/// Hex address, with or without a leading `0x` or `$`
+fn parse_address(s: &str) -> Result<u16, String> {
+    let s = s.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(s, 16).map_err(|_| "expected a hex address from 0 to FFFF".to_string())
 }
 
 pub fn main() {
     let args: Args = Args::parse();
-    let rom = if let Some(arg) = args.file {
+    let rom = if let Some(arg) = &args.file {
         fs::read(arg).expect("Usage: debugger [FILENAME]")
     } else {
         // vec![
@@ -52,43 +253,143 @@ pub fn main() {
         vec![0xa9, 0x69, 0x48, 0xa9, 0x42, 0x48, 0xa9, 0xbb, 0x48]
     };
 
-    let mut d = Debugger::new();
+    let acia_mapping = args.acia_address.map(|addr| {
+        match addr.checked_add(args.acia.size() - 1) {
+            Some(end) => Mapping::new(addr..=end),
+            None => {
+                eprintln!(
+                    "The {} registers don't fit at ${:04X}",
+                    args.acia.size(),
+                    addr
+                );
+                process::exit(2);
+            }
+        }
+    });
+    if args.port.is_none() && args.serial == "stdio" && !args.run {
+        eprintln!("The stdio serial backend needs --run, the debugger uses the terminal");
+        process::exit(2);
+    }
+    let (backend, serial) = match open_serial(&args) {
+        Ok(opened) => opened,
+        Err(err) => {
+            eprintln!("Could not open serial port: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Some(description) = &serial.description {
+        eprintln!("{}", description);
+    }
 
     // d.load(&rom, 0xC000);
     // d.load(&rom, 0xFFFF-255);
     // d.load(&rom, 0x8000);
-    d.load(&rom, 0);
+    let builder = Machine::builder()
+        .variant(args.cpu.into())
+        .cpu_clock(args.clock)
+        .cycle_accurate(args.cycle_accurate)
+        .block_cache(args.block_cache)
+        .image(&rom, args.load_address);
+
+    let builder = match args.acia {
+        Acia::Mc6850 => {
+            let acia = Acia6850::new(backend);
+            let mapping = acia_mapping.unwrap_or_else(|| Mapping::new(0xA000..=0xA001));
+            builder.serial_at(None).device(acia, vec![mapping])
+        }
+        Acia::Mos6551 => match acia_mapping {
+            Some(mapping) => builder.serial_backend(backend).serial_at(Some(mapping)),
+            None => builder.serial_backend(backend),
+        },
+    };
+    let machine = builder.build();
+
+    let mut d = Debugger::new(machine);
+    d.halt_on_brk = !args.no_halt_on_brk;
+    d.halt_on_loop = !args.no_halt_on_loop;
+
+    if let Some(path) = args.trace {
+        if path == Path::new("-") {
+            d.trace_to(stdout());
+        } else {
+            let file = fs::File::create(&path).expect("Could not create trace file");
+            d.trace_to(BufWriter::new(file));
+        }
+    }
+
     d.reset();
-    // d.cpu.lock().pc = 0x400;
-    // d.cpu.lock().pc = 0x4000;
+    // d.machine.lock().cpu.pc = 0x400;
+    // d.machine.lock().cpu.pc = 0x4000;
 
     if let Some(start) = args.start {
-        let start = start.strip_prefix("0x").unwrap_or(&start);
-        d.cpu.lock().pc = u16::from_str_radix(&start, 16).unwrap_or_default();
+        d.machine.lock().cpu.pc = start;
+    }
+
+    if let Some(path) = &args.load_state {
+        d.load_state(path).expect("Could not load state");
     }
 
     if args.maxspeed {
         d.max_speed = true;
     }
 
-    if args.run {
+    if args.run || args.script.is_some() {
         d.non_interactive_mode = true;
+        // Nothing can step back here, so don't pay for recording
+        d.set_history_limit(0);
 
         let start: SystemTime = SystemTime::now();
-        let handle = d.run();
-        handle.unwrap().join();
+        d.run();
+        if serial.hung_up.is_some() || serial.script.is_some() {
+            while d.state() == RunState::Running && !serial.finished() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            d.pause();
+        }
+        let reason = d.wait();
 
         let end = SystemTime::now().duration_since(start).unwrap();
 
+        if let Some(reason) = reason {
+            eprintln!("\nHalted: {}", reason);
+        }
+
+        if let Some(path) = &args.save_state {
+            d.save_state(path).expect("Could not save state");
+        }
+
         if args.verbose {
-            let cpu = d.cpu.lock();
+            let machine = d.machine.lock();
+            let cpu = &machine.cpu;
             println!("\n---");
             println!("Total cycles: \t\t{}", cpu.cycles());
             println!("Total instructions: \t{}", cpu.instructions);
             println!("Halted in {}.{}s.", end.as_secs(), end.subsec_millis());
         }
+
+        if let Some(script) = &serial.script {
+            let failure = match script.outcome() {
+                Outcome::Passed => None,
+                Outcome::Failed(reason) => Some(reason),
+                Outcome::Running => Some(match script.expecting() {
+                    Some(expecting) => format!(
+                        "machine halted while waiting for {:?}",
+                        String::from_utf8_lossy(&expecting)
+                    ),
+                    None => "machine halted before the script finished".to_string(),
+                }),
+            };
+            if let Some(reason) = failure {
+                eprintln!("\nScript failed: {}", reason);
+                process::exit(1);
+            }
+        }
     } else {
         let mut tui = Tui::new(d);
+        tui.message = serial.description;
+        if let Some(path) = args.save_state.or(args.load_state) {
+            tui.state_file = path;
+        }
         let _ = tui.show();
     }
 }
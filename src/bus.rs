@@ -1,63 +1,180 @@
-use std::{rc::Rc, cell::RefCell};
+use std::io::{Error, ErrorKind};
 
-use crate::{serial::Serial, stdin::Stdin, stdout::Stdout, mem::Memory, display::Display, io::IO, cpu::CPU6502};
+use crate::{
+    device::{Device, DeviceId, Mapping},
+    io::IO,
+    mem::Memory,
+};
+use crate::state::{SaveState, StateReader, StateWriter};
 
-const RAM_START: u16 = 0x0000;
-const RAM_END: u16 = 0x4FFF;
-const SERIAL_START: u16 = 0x5000;
-const SERIAL_END: u16 = 0x5FFF;
+pub const SERIAL_START: u16 = 0x5000;
+pub const SERIAL_END: u16 = 0x5FFF;
 // const SERIAL_START: u16 = 0x8400;
 // const SERIAL_END: u16 = 0x8403;
-const ROM_START: u16 = 0xC000;
-const ROM_END: u16 = 0xFFFF;
-
-// pub struct CpuBus {
-//     pub bus: Rc<RefCell<Bus>>,
-//     pub cpu: CPU6502,
-// }
-
-// impl CpuBus {
-//     pub fn new(bus: Bus) -> Self {
-//         let bus = Rc::new(RefCell::new(bus));
-//         let cpu = CPU6502::new(bus);
-//         Self {
-//             bus,
-//             cpu
-//         }
-//     }
-// }
 
+/// RAM across the whole address space, with devices mapped over it.
+///
+/// Where mappings overlap, the device that was mapped first wins.
 pub struct Bus {
     pub mem: Memory,
-    pub stdout: Stdout,
-    pub stdin: Stdin,
-    pub display: Display,
-    pub serial: Serial,
+    devices: Vec<Box<dyn Device>>,
+    mappings: Vec<(Mapping, DeviceId)>,
+    /// Pages with at least one device in them
+    mapped_pages: Vec<bool>,
+    /// Bumped whenever the mappings change
+    generation: u64,
+    /// When set, every RAM write is recorded here with the value it replaced
+    pub journal: Option<Vec<(u16, u8)>>,
 }
 
 impl Bus {
+    /// Just RAM. Attach devices to fill in the memory map.
+    pub fn new(mem: Memory) -> Self {
+        Self {
+            mem,
+            devices: vec![],
+            mappings: vec![],
+            mapped_pages: vec![false; 0x100],
+            generation: 0,
+            journal: None,
+        }
+    }
+
+    /// Add a device to the bus. It isn't reachable until it is mapped.
+    pub fn attach(&mut self, device: Box<dyn Device>) -> DeviceId {
+        self.devices.push(device);
+        DeviceId(self.devices.len() - 1)
+    }
+
+    /// Make a device appear at the addresses in `mapping`.
+    /// A device can be mapped at several places.
+    pub fn map(&mut self, id: DeviceId, mapping: Mapping) {
+        let (start, end) = (*mapping.range.start(), *mapping.range.end());
+        for page in (start >> 8)..=(end >> 8) {
+            self.mapped_pages[page as usize] = true;
+        }
+        self.mappings.push((mapping, id));
+        self.generation += 1;
+    }
+
+    pub fn device(&self, id: DeviceId) -> &dyn Device {
+        self.devices[id.0].as_ref()
+    }
+
+    pub fn device_mut(&mut self, id: DeviceId) -> &mut dyn Device {
+        self.devices[id.0].as_mut()
+    }
 
+    /// All attached devices, in the order they were attached.
+    pub fn devices(&self) -> impl Iterator<Item = (DeviceId, &dyn Device)> {
+        self.devices
+            .iter()
+            .enumerate()
+            .map(|(i, device)| (DeviceId(i), device.as_ref()))
+    }
+
+    pub fn mappings(&self) -> &[(Mapping, DeviceId)] {
+        &self.mappings
+    }
+
+    /// The whole address space as the CPU sees it, read with `peek`.
+    pub fn peek_all(&self) -> Vec<u8> {
+        let mut memory = self.mem.0.to_vec();
+        for (page, _) in self.mapped_pages.iter().enumerate().filter(|(_, mapped)| **mapped) {
+            let start = page << 8;
+            for (addr, byte) in memory[start..start + 0x100].iter_mut().enumerate() {
+                *byte = self.peek((start + addr) as u16);
+            }
+        }
+        memory
+    }
+
+    /// Find the device at `addr` and the address it sees.
+    #[inline]
+    fn decode_(&self, addr: u16) -> Option<(usize, u16)> {
+        if !self.mapped_pages[(addr >> 8) as usize] {
+            return None;
+        }
+        self.mappings
+            .iter()
+            .find(|(mapping, _)| mapping.contains(addr))
+            .map(|(mapping, id)| (id.0, mapping.relative(addr)))
+    }
 }
 
 impl IO for Bus {
     fn read(&mut self, addr: u16) -> u8 {
-        match addr {
-            SERIAL_START..=SERIAL_END => {
-                self.serial.read(addr-SERIAL_START)
-            }
-            _ => {
-                self.mem.read(addr)
-            }
+        match self.decode_(addr) {
+            Some((device, addr)) => self.devices[device].read(addr),
+            None => self.mem.read(addr),
+        }
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        match self.decode_(addr) {
+            Some((device, addr)) => self.devices[device].peek(addr),
+            None => self.mem.peek(addr),
         }
     }
     fn write(&mut self, addr: u16, data: u8) {
-        match addr {
-            SERIAL_START..=SERIAL_END => {
-                self.serial.write(addr-SERIAL_START, data)
-            }
-            _ => {
+        match self.decode_(addr) {
+            Some((device, addr)) => self.devices[device].write(addr, data),
+            None => {
+                if let Some(journal) = &mut self.journal {
+                    journal.push((addr, self.mem.0[addr as usize]));
+                }
                 self.mem.write(addr, data)
             }
         }
     }
+    fn tick(&mut self, cycles: u32) {
+        for device in &mut self.devices {
+            device.tick(cycles);
+        }
+    }
+    fn irq(&self) -> bool {
+        self.devices.iter().any(|device| device.irq())
+    }
+    fn nmi(&self) -> bool {
+        self.devices.iter().any(|device| device.nmi())
+    }
+    fn map_generation(&self) -> u64 {
+        self.devices
+            .iter()
+            .fold(self.generation, |generation, device| {
+                generation.wrapping_add(device.map_generation())
+            })
+    }
+}
+
+/// The memory map itself isn't saved, only what is in it.
+/// A state can only be loaded into a machine with the same devices.
+impl SaveState for Bus {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.mem.save_state(w);
+        w.u32(self.devices.len() as u32);
+        for device in &self.devices {
+            w.bytes(device.name().as_bytes());
+            device.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        let mismatch = || Error::new(ErrorKind::InvalidData, "save state is for different devices");
+
+        if r.version() < 2 {
+            return Err(Error::new(ErrorKind::InvalidData, "save state is from an older version"));
+        }
+
+        self.mem.load_state(r)?;
+        if r.u32()? as usize != self.devices.len() {
+            return Err(mismatch());
+        }
+        for device in &mut self.devices {
+            if r.bytes()? != device.name().as_bytes() {
+                return Err(mismatch());
+            }
+            device.load_state(r)?;
+        }
+        Ok(())
+    }
 }
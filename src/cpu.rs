@@ -1,5 +1,12 @@
 use colored::{ColoredString, Colorize};
-use crate::io::IO;
+use std::{fmt, io};
+use self::blocks::{Addressing, BlockCache, Handler};
+use crate::{
+    io::IO,
+    state::{SaveState, StateReader, StateWriter},
+};
+
+mod blocks;
 
 const DEBUG: bool = false;
 
@@ -12,7 +19,7 @@ bitflags! {
         const V = 1 << 6;
         /// Unused
         const U = 1 << 5;
-        /// Breakpoint
+        /// Break. Only exists in copies of P pushed by BRK and PHP.
         const B = 1 << 4;
         /// Binary-coded decimal (BCD)
         const D = 1 << 3;
@@ -30,8 +37,12 @@ bitflags! {
 /// The 6502 uses a descending stack that grows downward.
 const STACK: u16 = 0x0100;
 
+/// Constant ORed into A by the unstable `XAA` and `LXA` opcodes.
+/// The real value depends on the individual chip; 0xEE is the most common.
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
 /// Each instruction on the 6502 uses one of thirteen
-/// memory addressing modes. These determine how the operand (if any) is looked up.
+/// memory addressing modes. The 65C02 adds three more. These determine how the operand (if any) is looked up.
 ///
 /// ### References
 ///
@@ -40,7 +51,7 @@ const STACK: u16 = 0x0100;
 /// - https://lowendgaming.neocities.org/6502_addressing_modes.htm
 /// - https://slark.me/c64-downloads/6502-addressing-modes.pdf
 /// - https://www.pagetable.com/c64ref/6502/?tab=3
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
     /// Implied
     ///
@@ -94,10 +105,89 @@ pub enum Mode {
     ///
     /// e.g. `JMP ($AAAA)`
     IND,
+    /// Zero Page Indirect (65C02)
+    ///
+    /// e.g. `LDA ($AA)`
+    ZPI,
+    /// Absolute Indexed Indirect (65C02)
+    ///
+    /// e.g. `JMP ($AAAA,X)`
+    IAX,
+    /// Zero Page, Relative (65C02)
+    ///
+    /// e.g. `BBR0 $AA,$BBBB`
+    ZPR,
 }
 
-#[derive(Debug, Clone, Copy)]
-/// All 55 opcodes on the 6502 plus `XXX`, which represenst an illegal opcode.
+impl Mode {
+    /// Length in bytes of an instruction using this mode, including the opcode.
+    pub fn size(&self) -> u16 {
+        match self {
+            Mode::IMP | Mode::ACC => 1,
+            Mode::ABS | Mode::ABX | Mode::ABY | Mode::IND | Mode::IAX | Mode::ZPR => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// CPU variant
+///
+/// Selects the instruction set and the handful of behaviors which
+/// differ between the original NMOS 6502 and the CMOS 65C02.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// MOS/NMOS 6502, including undocumented opcodes
+    NMOS6502,
+    /// WDC 65C02 (CMOS), including the Rockwell bit instructions
+    WDC65C02,
+}
+
+/// Why execution stopped.
+///
+/// The CPU itself only reports `Jam`, `Stop` and `InfiniteLoop`.
+/// The other reasons are raised by the debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    /// `BRK` at the given address, when the debugger is set to halt on `BRK`
+    Break(u16),
+    /// Undocumented `JAM` (`KIL`) opcode at the given address
+    Jam(u16),
+    /// 65C02 `STP` at the given address
+    Stop(u16),
+    /// Breakpoint at the given address
+    Breakpoint(u16),
+    /// Write to a watched address
+    Watchpoint(u16),
+    /// The cycle limit was reached after the given number of cycles
+    CycleLimit(u64),
+    /// The instruction limit was reached after the given number of instructions
+    InstructionLimit(usize),
+    /// Branch or jump to itself (e.g. `JMP *` or `BNE *`) at the given address,
+    /// with IRQs masked and no NMI on the way
+    InfiniteLoop(u16),
+}
+
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HaltReason::Break(addr) => write!(f, "BRK at ${:04X}", addr),
+            HaltReason::Jam(addr) => write!(f, "JAM at ${:04X}", addr),
+            HaltReason::Stop(addr) => write!(f, "STP at ${:04X}", addr),
+            HaltReason::Breakpoint(addr) => write!(f, "breakpoint at ${:04X}", addr),
+            HaltReason::Watchpoint(addr) => write!(f, "watchpoint on ${:04X}", addr),
+            HaltReason::CycleLimit(cycles) => write!(f, "cycle limit ({} cycles)", cycles),
+            HaltReason::InstructionLimit(instructions) => {
+                write!(f, "instruction limit ({} instructions)", instructions)
+            }
+            HaltReason::InfiniteLoop(addr) => write!(f, "infinite loop at ${:04X}", addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+/// All 55 opcodes on the 6502 plus the undocumented ("illegal") NMOS opcodes
+/// and the opcodes added by the 65C02.
 pub enum Opcode {
     /// `ADC` - Add with Carry
     ADC,
@@ -220,271 +310,767 @@ pub enum Opcode {
     TXS,
     /// `TYA` - Transfer Y to Accumulator
     TYA,
-    /// `XXX` - Illegal Opcode
-    XXX,
+
+    // Undocumented NMOS opcodes
+    //
+    // https://www.masswerk.at/6502/6502_instruction_set.html#illegals
+    // https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+    /// `AHX` - Store A AND X AND (High Byte + 1) (also `SHA`). Unstable.
+    AHX,
+    /// `ALR` - AND Immediate then Logical Shift Right A (also `ASR`)
+    ALR,
+    /// `ANC` - AND Immediate then Copy N to C
+    ANC,
+    /// `ARR` - AND Immediate then Rotate Right A
+    ARR,
+    /// `DCP` - Decrement then Compare (also `DCM`)
+    DCP,
+    /// `ISC` - Increment then Subtract with Carry (also `ISB`, `INS`)
+    ISC,
+    /// `JAM` - Lock up the processor (also `KIL`, `HLT`)
+    JAM,
+    /// `LAS` - Load A, X and Stack Pointer with Memory AND Stack Pointer (also `LAR`)
+    LAS,
+    /// `LAX` - Load Accumulator and X
+    LAX,
+    /// `LXA` - Load Accumulator and X with Immediate (also `LAX #`, `ATX`). Unstable.
+    LXA,
+    /// `RLA` - Rotate Left then AND
+    RLA,
+    /// `RRA` - Rotate Right then Add with Carry
+    RRA,
+    /// `SAX` - Store A AND X (also `AXS`, `AAX`)
+    SAX,
+    /// `SBX` - Subtract Immediate from A AND X into X (also `AXS`)
+    SBX,
+    /// `SHX` - Store X AND (High Byte + 1) (also `SXA`). Unstable.
+    SHX,
+    /// `SHY` - Store Y AND (High Byte + 1) (also `SYA`). Unstable.
+    SHY,
+    /// `SLO` - Arithmetic Shift Left then OR (also `ASO`)
+    SLO,
+    /// `SRE` - Logical Shift Right then Exclusive OR (also `LSE`)
+    SRE,
+    /// `TAS` - Transfer A AND X to Stack Pointer, then store like `AHX` (also `SHS`). Unstable.
+    TAS,
+    /// `XAA` - Transfer X to A then AND Immediate (also `ANE`). Unstable.
+    XAA,
+
+    // 65C02 opcodes
+    //
+    // http://www.6502.org/tutorials/65c02opcodes.html
+    /// `BRA` - Branch Always
+    BRA,
+    /// `DEC (Accumulator)` - Decrement
+    DEC_A,
+    /// `INC (Accumulator)` - Increment
+    INC_A,
+    /// `PHX` - Push X to Stack
+    PHX,
+    /// `PHY` - Push Y to Stack
+    PHY,
+    /// `PLX` - Pull X from Stack
+    PLX,
+    /// `PLY` - Pull Y from Stack
+    PLY,
+    /// `STP` - Stop the Processor
+    STP,
+    /// `STZ` - Store Zero
+    STZ,
+    /// `TRB` - Test and Reset Bits
+    TRB,
+    /// `TSB` - Test and Set Bits
+    TSB,
+    /// `WAI` - Wait for Interrupt
+    WAI,
+    /// `BBR0` - Branch on Bit 0 Reset
+    BBR0,
+    /// `BBR1` - Branch on Bit 1 Reset
+    BBR1,
+    /// `BBR2` - Branch on Bit 2 Reset
+    BBR2,
+    /// `BBR3` - Branch on Bit 3 Reset
+    BBR3,
+    /// `BBR4` - Branch on Bit 4 Reset
+    BBR4,
+    /// `BBR5` - Branch on Bit 5 Reset
+    BBR5,
+    /// `BBR6` - Branch on Bit 6 Reset
+    BBR6,
+    /// `BBR7` - Branch on Bit 7 Reset
+    BBR7,
+    /// `BBS0` - Branch on Bit 0 Set
+    BBS0,
+    /// `BBS1` - Branch on Bit 1 Set
+    BBS1,
+    /// `BBS2` - Branch on Bit 2 Set
+    BBS2,
+    /// `BBS3` - Branch on Bit 3 Set
+    BBS3,
+    /// `BBS4` - Branch on Bit 4 Set
+    BBS4,
+    /// `BBS5` - Branch on Bit 5 Set
+    BBS5,
+    /// `BBS6` - Branch on Bit 6 Set
+    BBS6,
+    /// `BBS7` - Branch on Bit 7 Set
+    BBS7,
+    /// `RMB0` - Reset Memory Bit 0
+    RMB0,
+    /// `RMB1` - Reset Memory Bit 1
+    RMB1,
+    /// `RMB2` - Reset Memory Bit 2
+    RMB2,
+    /// `RMB3` - Reset Memory Bit 3
+    RMB3,
+    /// `RMB4` - Reset Memory Bit 4
+    RMB4,
+    /// `RMB5` - Reset Memory Bit 5
+    RMB5,
+    /// `RMB6` - Reset Memory Bit 6
+    RMB6,
+    /// `RMB7` - Reset Memory Bit 7
+    RMB7,
+    /// `SMB0` - Set Memory Bit 0
+    SMB0,
+    /// `SMB1` - Set Memory Bit 1
+    SMB1,
+    /// `SMB2` - Set Memory Bit 2
+    SMB2,
+    /// `SMB3` - Set Memory Bit 3
+    SMB3,
+    /// `SMB4` - Set Memory Bit 4
+    SMB4,
+    /// `SMB5` - Set Memory Bit 5
+    SMB5,
+    /// `SMB6` - Set Memory Bit 6
+    SMB6,
+    /// `SMB7` - Set Memory Bit 7
+    SMB7,
+}
+
+impl Opcode {
+    /// Undocumented NMOS opcodes. The extra NOPs and SBC $EB are
+    /// documented mnemonics, so they are not included.
+    pub fn is_undocumented(&self) -> bool {
+        matches!(
+            self,
+            Opcode::AHX
+                | Opcode::ALR
+                | Opcode::ANC
+                | Opcode::ARR
+                | Opcode::DCP
+                | Opcode::ISC
+                | Opcode::JAM
+                | Opcode::LAS
+                | Opcode::LAX
+                | Opcode::LXA
+                | Opcode::RLA
+                | Opcode::RRA
+                | Opcode::SAX
+                | Opcode::SBX
+                | Opcode::SHX
+                | Opcode::SHY
+                | Opcode::SLO
+                | Opcode::SRE
+                | Opcode::TAS
+                | Opcode::XAA
+        )
+    }
+}
+
+impl fmt::Display for Opcode {
+    /// Assembler mnemonic, e.g. `ASL` for `ASL_A`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{:?}", self);
+        f.write_str(name.strip_suffix("_A").unwrap_or(&name))
+    }
 }
 
 pub type Instruction = (Opcode, Mode, u8, bool);
 
+/// NMOS 6502 instruction set, including undocumented opcodes.
 pub const INSTRUCTIONS: [Instruction; 256] = [
     (Opcode::BRK, Mode::IMP, 7, false),
     (Opcode::ORA, Mode::ZIX, 6, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
-    (Opcode::NOP, Mode::IMP, 3, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::SLO, Mode::ZIX, 8, false),
+    (Opcode::NOP, Mode::ZPG, 3, false),
     (Opcode::ORA, Mode::ZPG, 3, false),
     (Opcode::ASL, Mode::ZPG, 5, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
+    (Opcode::SLO, Mode::ZPG, 5, false),
     (Opcode::PHP, Mode::IMP, 3, false),
     (Opcode::ORA, Mode::IMM, 2, false),
     (Opcode::ASL_A, Mode::ACC, 2, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::ANC, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::ABS, 4, false),
     (Opcode::ORA, Mode::ABS, 4, false),
     (Opcode::ASL, Mode::ABS, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::SLO, Mode::ABS, 6, false),
     (Opcode::BPL, Mode::REL, 2, false),
     (Opcode::ORA, Mode::ZIY, 5, true),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::SLO, Mode::ZIY, 8, false),
+    (Opcode::NOP, Mode::ZPX, 4, false),
     (Opcode::ORA, Mode::ZPX, 4, false),
     (Opcode::ASL, Mode::ZPX, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::SLO, Mode::ZPX, 6, false),
     (Opcode::CLC, Mode::IMP, 2, false),
     (Opcode::ORA, Mode::ABY, 4, true),
     (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::SLO, Mode::ABY, 7, false),
+    (Opcode::NOP, Mode::ABX, 4, true),
     (Opcode::ORA, Mode::ABX, 4, true),
     (Opcode::ASL, Mode::ABX, 7, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
+    (Opcode::SLO, Mode::ABX, 7, false),
     (Opcode::JSR, Mode::ABS, 6, false),
     (Opcode::AND, Mode::ZIX, 6, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::RLA, Mode::ZIX, 8, false),
     (Opcode::BIT, Mode::ZPG, 3, false),
     (Opcode::AND, Mode::ZPG, 3, false),
     (Opcode::ROL, Mode::ZPG, 5, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
+    (Opcode::RLA, Mode::ZPG, 5, false),
     (Opcode::PLP, Mode::IMP, 4, false),
     (Opcode::AND, Mode::IMM, 2, false),
     (Opcode::ROL_A, Mode::ACC, 2, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
+    (Opcode::ANC, Mode::IMM, 2, false),
     (Opcode::BIT, Mode::ABS, 4, false),
     (Opcode::AND, Mode::ABS, 4, false),
     (Opcode::ROL, Mode::ABS, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::RLA, Mode::ABS, 6, false),
     (Opcode::BMI, Mode::REL, 2, false),
     (Opcode::AND, Mode::ZIY, 5, true),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::RLA, Mode::ZIY, 8, false),
+    (Opcode::NOP, Mode::ZPX, 4, false),
     (Opcode::AND, Mode::ZPX, 4, false),
     (Opcode::ROL, Mode::ZPX, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::RLA, Mode::ZPX, 6, false),
     (Opcode::SEC, Mode::IMP, 2, false),
     (Opcode::AND, Mode::ABY, 4, true),
     (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::RLA, Mode::ABY, 7, false),
+    (Opcode::NOP, Mode::ABX, 4, true),
     (Opcode::AND, Mode::ABX, 4, true),
     (Opcode::ROL, Mode::ABX, 7, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
+    (Opcode::RLA, Mode::ABX, 7, false),
     (Opcode::RTI, Mode::IMP, 6, false),
     (Opcode::EOR, Mode::ZIX, 6, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
-    (Opcode::NOP, Mode::IMP, 3, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::SRE, Mode::ZIX, 8, false),
+    (Opcode::NOP, Mode::ZPG, 3, false),
     (Opcode::EOR, Mode::ZPG, 3, false),
     (Opcode::LSR, Mode::ZPG, 5, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
+    (Opcode::SRE, Mode::ZPG, 5, false),
     (Opcode::PHA, Mode::IMP, 3, false),
     (Opcode::EOR, Mode::IMM, 2, false),
     (Opcode::LSR_A, Mode::ACC, 2, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
+    (Opcode::ALR, Mode::IMM, 2, false),
     (Opcode::JMP, Mode::ABS, 3, false),
     (Opcode::EOR, Mode::ABS, 4, false),
     (Opcode::LSR, Mode::ABS, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::SRE, Mode::ABS, 6, false),
     (Opcode::BVC, Mode::REL, 2, true),
     (Opcode::EOR, Mode::ZIY, 5, true),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::SRE, Mode::ZIY, 8, false),
+    (Opcode::NOP, Mode::ZPX, 4, false),
     (Opcode::EOR, Mode::ZPX, 4, false),
     (Opcode::LSR, Mode::ZPX, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::SRE, Mode::ZPX, 6, false),
     (Opcode::CLI, Mode::IMP, 2, false),
     (Opcode::EOR, Mode::ABY, 4, true),
     (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::SRE, Mode::ABY, 7, false),
+    (Opcode::NOP, Mode::ABX, 4, true),
     (Opcode::EOR, Mode::ABX, 4, true),
     (Opcode::LSR, Mode::ABX, 7, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
+    (Opcode::SRE, Mode::ABX, 7, false),
     (Opcode::RTS, Mode::IMP, 6, false),
     (Opcode::ADC, Mode::ZIX, 6, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
-    (Opcode::NOP, Mode::IMP, 3, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::RRA, Mode::ZIX, 8, false),
+    (Opcode::NOP, Mode::ZPG, 3, false),
     (Opcode::ADC, Mode::ZPG, 3, false),
     (Opcode::ROR, Mode::ZPG, 5, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
+    (Opcode::RRA, Mode::ZPG, 5, false),
     (Opcode::PLA, Mode::IMP, 4, false),
     (Opcode::ADC, Mode::IMM, 2, false),
     (Opcode::ROR_A, Mode::ACC, 2, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
+    (Opcode::ARR, Mode::IMM, 2, false),
     (Opcode::JMP, Mode::IND, 5, false),
     (Opcode::ADC, Mode::ABS, 4, false),
     (Opcode::ROR, Mode::ABS, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::RRA, Mode::ABS, 6, false),
     (Opcode::BVS, Mode::REL, 2, true),
     (Opcode::ADC, Mode::ZIY, 5, true),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::RRA, Mode::ZIY, 8, false),
+    (Opcode::NOP, Mode::ZPX, 4, false),
     (Opcode::ADC, Mode::ZPX, 4, false),
     (Opcode::ROR, Mode::ZPX, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::RRA, Mode::ZPX, 6, false),
     (Opcode::SEI, Mode::IMP, 2, false),
     (Opcode::ADC, Mode::ABY, 4, true),
     (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::RRA, Mode::ABY, 7, false),
+    (Opcode::NOP, Mode::ABX, 4, true),
     (Opcode::ADC, Mode::ABX, 4, true),
     (Opcode::ROR, Mode::ABX, 7, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
-    (Opcode::NOP, Mode::IMP, 2, false),
+    (Opcode::RRA, Mode::ABX, 7, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
     (Opcode::STA, Mode::ZIX, 6, false),
-    (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::SAX, Mode::ZIX, 6, false),
     (Opcode::STY, Mode::ZPG, 3, false),
     (Opcode::STA, Mode::ZPG, 3, false),
     (Opcode::STX, Mode::ZPG, 3, false),
-    (Opcode::XXX, Mode::IMP, 3, false),
+    (Opcode::SAX, Mode::ZPG, 3, false),
     (Opcode::DEY, Mode::IMP, 2, false),
-    (Opcode::NOP, Mode::IMP, 2, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
     (Opcode::TXA, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
+    (Opcode::XAA, Mode::IMM, 2, false),
     (Opcode::STY, Mode::ABS, 4, false),
     (Opcode::STA, Mode::ABS, 4, false),
     (Opcode::STX, Mode::ABS, 4, false),
-    (Opcode::XXX, Mode::IMP, 4, false),
+    (Opcode::SAX, Mode::ABS, 4, false),
     (Opcode::BCC, Mode::REL, 2, true),
     (Opcode::STA, Mode::ZIY, 6, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::AHX, Mode::ZIY, 6, false),
     (Opcode::STY, Mode::ZPX, 4, false),
     (Opcode::STA, Mode::ZPX, 4, false),
     (Opcode::STX, Mode::ZPY, 4, false),
-    (Opcode::XXX, Mode::IMP, 4, false),
+    (Opcode::SAX, Mode::ZPY, 4, false),
     (Opcode::TYA, Mode::IMP, 2, false),
     (Opcode::STA, Mode::ABY, 5, false),
     (Opcode::TXS, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
-    (Opcode::NOP, Mode::IMP, 5, false),
+    (Opcode::TAS, Mode::ABY, 5, false),
+    (Opcode::SHY, Mode::ABX, 5, false),
     (Opcode::STA, Mode::ABX, 5, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
+    (Opcode::SHX, Mode::ABY, 5, false),
+    (Opcode::AHX, Mode::ABY, 5, false),
     (Opcode::LDY, Mode::IMM, 2, false),
     (Opcode::LDA, Mode::ZIX, 6, false),
     (Opcode::LDX, Mode::IMM, 2, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::LAX, Mode::ZIX, 6, false),
     (Opcode::LDY, Mode::ZPG, 3, false),
     (Opcode::LDA, Mode::ZPG, 3, false),
     (Opcode::LDX, Mode::ZPG, 3, false),
-    (Opcode::XXX, Mode::IMP, 3, false),
+    (Opcode::LAX, Mode::ZPG, 3, false),
     (Opcode::TAY, Mode::IMP, 2, false),
     (Opcode::LDA, Mode::IMM, 2, false),
     (Opcode::TAX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
+    (Opcode::LXA, Mode::IMM, 2, false),
     (Opcode::LDY, Mode::ABS, 4, false),
     (Opcode::LDA, Mode::ABS, 4, false),
     (Opcode::LDX, Mode::ABS, 4, false),
-    (Opcode::XXX, Mode::IMP, 4, false),
+    (Opcode::LAX, Mode::ABS, 4, false),
     (Opcode::BCS, Mode::REL, 2, true),
     (Opcode::LDA, Mode::ZIY, 5, true),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::LAX, Mode::ZIY, 5, true),
     (Opcode::LDY, Mode::ZPX, 4, false),
     (Opcode::LDA, Mode::ZPX, 4, false),
     (Opcode::LDX, Mode::ZPY, 4, false),
-    (Opcode::XXX, Mode::IMP, 4, false),
+    (Opcode::LAX, Mode::ZPY, 4, false),
     (Opcode::CLV, Mode::IMP, 2, false),
     (Opcode::LDA, Mode::ABY, 4, true),
     (Opcode::TSX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 4, false),
+    (Opcode::LAS, Mode::ABY, 4, true),
     (Opcode::LDY, Mode::ABX, 4, true),
     (Opcode::LDA, Mode::ABX, 4, true),
     (Opcode::LDX, Mode::ABY, 4, true),
-    (Opcode::XXX, Mode::IMP, 4, false),
+    (Opcode::LAX, Mode::ABY, 4, true),
     (Opcode::CPY, Mode::IMM, 2, false),
     (Opcode::CMP, Mode::ZIX, 6, false),
-    (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::DCP, Mode::ZIX, 8, false),
     (Opcode::CPY, Mode::ZPG, 3, false),
     (Opcode::CMP, Mode::ZPG, 3, false),
     (Opcode::DEC, Mode::ZPG, 5, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
+    (Opcode::DCP, Mode::ZPG, 5, false),
     (Opcode::INY, Mode::IMP, 2, false),
     (Opcode::CMP, Mode::IMM, 2, false),
     (Opcode::DEX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 2, false),
+    (Opcode::SBX, Mode::IMM, 2, false),
     (Opcode::CPY, Mode::ABS, 4, false),
     (Opcode::CMP, Mode::ABS, 4, false),
     (Opcode::DEC, Mode::ABS, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::DCP, Mode::ABS, 6, false),
     (Opcode::BNE, Mode::REL, 2, true),
     (Opcode::CMP, Mode::ZIY, 5, true),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::DCP, Mode::ZIY, 8, false),
+    (Opcode::NOP, Mode::ZPX, 4, false),
     (Opcode::CMP, Mode::ZPX, 4, false),
     (Opcode::DEC, Mode::ZPX, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::DCP, Mode::ZPX, 6, false),
     (Opcode::CLD, Mode::IMP, 2, false),
     (Opcode::CMP, Mode::ABY, 4, true),
     (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::DCP, Mode::ABY, 7, false),
+    (Opcode::NOP, Mode::ABX, 4, true),
     (Opcode::CMP, Mode::ABX, 4, true),
     (Opcode::DEC, Mode::ABX, 7, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
+    (Opcode::DCP, Mode::ABX, 7, false),
     (Opcode::CPX, Mode::IMM, 2, false),
     (Opcode::SBC, Mode::ZIX, 6, false),
-    (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::ISC, Mode::ZIX, 8, false),
     (Opcode::CPX, Mode::ZPG, 3, false),
     (Opcode::SBC, Mode::ZPG, 3, false),
     (Opcode::INC, Mode::ZPG, 5, false),
-    (Opcode::XXX, Mode::IMP, 5, false),
+    (Opcode::ISC, Mode::ZPG, 5, false),
     (Opcode::INX, Mode::IMP, 2, false),
     (Opcode::SBC, Mode::IMM, 2, false),
     (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::SBC, Mode::IMP, 2, false),
+    (Opcode::SBC, Mode::IMM, 2, false),
     (Opcode::CPX, Mode::ABS, 4, false),
     (Opcode::SBC, Mode::ABS, 4, false),
     (Opcode::INC, Mode::ABS, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::ISC, Mode::ABS, 6, false),
     (Opcode::BEQ, Mode::REL, 2, true),
     (Opcode::SBC, Mode::ZIY, 5, true),
-    (Opcode::XXX, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 8, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::JAM, Mode::IMP, 2, false),
+    (Opcode::ISC, Mode::ZIY, 8, false),
+    (Opcode::NOP, Mode::ZPX, 4, false),
     (Opcode::SBC, Mode::ZPX, 4, false),
     (Opcode::INC, Mode::ZPX, 6, false),
-    (Opcode::XXX, Mode::IMP, 6, false),
+    (Opcode::ISC, Mode::ZPX, 6, false),
     (Opcode::SED, Mode::IMP, 2, false),
     (Opcode::SBC, Mode::ABY, 4, true),
     (Opcode::NOP, Mode::IMP, 2, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
-    (Opcode::NOP, Mode::IMP, 4, false),
+    (Opcode::ISC, Mode::ABY, 7, false),
+    (Opcode::NOP, Mode::ABX, 4, true),
+    (Opcode::SBC, Mode::ABX, 4, true),
+    (Opcode::INC, Mode::ABX, 7, false),
+    (Opcode::ISC, Mode::ABX, 7, false),
+];
+
+/// WDC 65C02 instruction set.
+///
+/// Includes the Rockwell bit manipulation instructions (`RMB`, `SMB`, `BBR`, `BBS`)
+/// as well as `WAI` and `STP`. Unused opcodes are NOPs of varying length.
+pub const INSTRUCTIONS_65C02: [Instruction; 256] = [
+    (Opcode::BRK, Mode::IMP, 7, false),
+    (Opcode::ORA, Mode::ZIX, 6, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::TSB, Mode::ZPG, 5, false),
+    (Opcode::ORA, Mode::ZPG, 3, false),
+    (Opcode::ASL, Mode::ZPG, 5, false),
+    (Opcode::RMB0, Mode::ZPG, 5, false),
+    (Opcode::PHP, Mode::IMP, 3, false),
+    (Opcode::ORA, Mode::IMM, 2, false),
+    (Opcode::ASL_A, Mode::ACC, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::TSB, Mode::ABS, 6, false),
+    (Opcode::ORA, Mode::ABS, 4, false),
+    (Opcode::ASL, Mode::ABS, 6, false),
+    (Opcode::BBR0, Mode::ZPR, 5, false),
+    (Opcode::BPL, Mode::REL, 2, false),
+    (Opcode::ORA, Mode::ZIY, 5, true),
+    (Opcode::ORA, Mode::ZPI, 5, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::TRB, Mode::ZPG, 5, false),
+    (Opcode::ORA, Mode::ZPX, 4, false),
+    (Opcode::ASL, Mode::ZPX, 6, false),
+    (Opcode::RMB1, Mode::ZPG, 5, false),
+    (Opcode::CLC, Mode::IMP, 2, false),
+    (Opcode::ORA, Mode::ABY, 4, true),
+    (Opcode::INC_A, Mode::ACC, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::TRB, Mode::ABS, 6, false),
+    (Opcode::ORA, Mode::ABX, 4, true),
+    (Opcode::ASL, Mode::ABX, 6, true),
+    (Opcode::BBR1, Mode::ZPR, 5, false),
+    (Opcode::JSR, Mode::ABS, 6, false),
+    (Opcode::AND, Mode::ZIX, 6, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::BIT, Mode::ZPG, 3, false),
+    (Opcode::AND, Mode::ZPG, 3, false),
+    (Opcode::ROL, Mode::ZPG, 5, false),
+    (Opcode::RMB2, Mode::ZPG, 5, false),
+    (Opcode::PLP, Mode::IMP, 4, false),
+    (Opcode::AND, Mode::IMM, 2, false),
+    (Opcode::ROL_A, Mode::ACC, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::BIT, Mode::ABS, 4, false),
+    (Opcode::AND, Mode::ABS, 4, false),
+    (Opcode::ROL, Mode::ABS, 6, false),
+    (Opcode::BBR2, Mode::ZPR, 5, false),
+    (Opcode::BMI, Mode::REL, 2, false),
+    (Opcode::AND, Mode::ZIY, 5, true),
+    (Opcode::AND, Mode::ZPI, 5, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::BIT, Mode::ZPX, 4, false),
+    (Opcode::AND, Mode::ZPX, 4, false),
+    (Opcode::ROL, Mode::ZPX, 6, false),
+    (Opcode::RMB3, Mode::ZPG, 5, false),
+    (Opcode::SEC, Mode::IMP, 2, false),
+    (Opcode::AND, Mode::ABY, 4, true),
+    (Opcode::DEC_A, Mode::ACC, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::BIT, Mode::ABX, 4, true),
+    (Opcode::AND, Mode::ABX, 4, true),
+    (Opcode::ROL, Mode::ABX, 6, true),
+    (Opcode::BBR3, Mode::ZPR, 5, false),
+    (Opcode::RTI, Mode::IMP, 6, false),
+    (Opcode::EOR, Mode::ZIX, 6, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::NOP, Mode::ZPG, 3, false),
+    (Opcode::EOR, Mode::ZPG, 3, false),
+    (Opcode::LSR, Mode::ZPG, 5, false),
+    (Opcode::RMB4, Mode::ZPG, 5, false),
+    (Opcode::PHA, Mode::IMP, 3, false),
+    (Opcode::EOR, Mode::IMM, 2, false),
+    (Opcode::LSR_A, Mode::ACC, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::JMP, Mode::ABS, 3, false),
+    (Opcode::EOR, Mode::ABS, 4, false),
+    (Opcode::LSR, Mode::ABS, 6, false),
+    (Opcode::BBR4, Mode::ZPR, 5, false),
+    (Opcode::BVC, Mode::REL, 2, true),
+    (Opcode::EOR, Mode::ZIY, 5, true),
+    (Opcode::EOR, Mode::ZPI, 5, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::NOP, Mode::ZPX, 4, false),
+    (Opcode::EOR, Mode::ZPX, 4, false),
+    (Opcode::LSR, Mode::ZPX, 6, false),
+    (Opcode::RMB5, Mode::ZPG, 5, false),
+    (Opcode::CLI, Mode::IMP, 2, false),
+    (Opcode::EOR, Mode::ABY, 4, true),
+    (Opcode::PHY, Mode::IMP, 3, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::NOP, Mode::ABS, 8, false),
+    (Opcode::EOR, Mode::ABX, 4, true),
+    (Opcode::LSR, Mode::ABX, 6, true),
+    (Opcode::BBR5, Mode::ZPR, 5, false),
+    (Opcode::RTS, Mode::IMP, 6, false),
+    (Opcode::ADC, Mode::ZIX, 6, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::STZ, Mode::ZPG, 3, false),
+    (Opcode::ADC, Mode::ZPG, 3, false),
+    (Opcode::ROR, Mode::ZPG, 5, false),
+    (Opcode::RMB6, Mode::ZPG, 5, false),
+    (Opcode::PLA, Mode::IMP, 4, false),
+    (Opcode::ADC, Mode::IMM, 2, false),
+    (Opcode::ROR_A, Mode::ACC, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::JMP, Mode::IND, 6, false),
+    (Opcode::ADC, Mode::ABS, 4, false),
+    (Opcode::ROR, Mode::ABS, 6, false),
+    (Opcode::BBR6, Mode::ZPR, 5, false),
+    (Opcode::BVS, Mode::REL, 2, true),
+    (Opcode::ADC, Mode::ZIY, 5, true),
+    (Opcode::ADC, Mode::ZPI, 5, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::STZ, Mode::ZPX, 4, false),
+    (Opcode::ADC, Mode::ZPX, 4, false),
+    (Opcode::ROR, Mode::ZPX, 6, false),
+    (Opcode::RMB7, Mode::ZPG, 5, false),
+    (Opcode::SEI, Mode::IMP, 2, false),
+    (Opcode::ADC, Mode::ABY, 4, true),
+    (Opcode::PLY, Mode::IMP, 4, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::JMP, Mode::IAX, 6, false),
+    (Opcode::ADC, Mode::ABX, 4, true),
+    (Opcode::ROR, Mode::ABX, 6, true),
+    (Opcode::BBR7, Mode::ZPR, 5, false),
+    (Opcode::BRA, Mode::REL, 3, false),
+    (Opcode::STA, Mode::ZIX, 6, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::STY, Mode::ZPG, 3, false),
+    (Opcode::STA, Mode::ZPG, 3, false),
+    (Opcode::STX, Mode::ZPG, 3, false),
+    (Opcode::SMB0, Mode::ZPG, 5, false),
+    (Opcode::DEY, Mode::IMP, 2, false),
+    (Opcode::BIT, Mode::IMM, 2, false),
+    (Opcode::TXA, Mode::IMP, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::STY, Mode::ABS, 4, false),
+    (Opcode::STA, Mode::ABS, 4, false),
+    (Opcode::STX, Mode::ABS, 4, false),
+    (Opcode::BBS0, Mode::ZPR, 5, false),
+    (Opcode::BCC, Mode::REL, 2, true),
+    (Opcode::STA, Mode::ZIY, 6, false),
+    (Opcode::STA, Mode::ZPI, 5, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::STY, Mode::ZPX, 4, false),
+    (Opcode::STA, Mode::ZPX, 4, false),
+    (Opcode::STX, Mode::ZPY, 4, false),
+    (Opcode::SMB1, Mode::ZPG, 5, false),
+    (Opcode::TYA, Mode::IMP, 2, false),
+    (Opcode::STA, Mode::ABY, 5, false),
+    (Opcode::TXS, Mode::IMP, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::STZ, Mode::ABS, 4, false),
+    (Opcode::STA, Mode::ABX, 5, false),
+    (Opcode::STZ, Mode::ABX, 5, false),
+    (Opcode::BBS1, Mode::ZPR, 5, false),
+    (Opcode::LDY, Mode::IMM, 2, false),
+    (Opcode::LDA, Mode::ZIX, 6, false),
+    (Opcode::LDX, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::LDY, Mode::ZPG, 3, false),
+    (Opcode::LDA, Mode::ZPG, 3, false),
+    (Opcode::LDX, Mode::ZPG, 3, false),
+    (Opcode::SMB2, Mode::ZPG, 5, false),
+    (Opcode::TAY, Mode::IMP, 2, false),
+    (Opcode::LDA, Mode::IMM, 2, false),
+    (Opcode::TAX, Mode::IMP, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::LDY, Mode::ABS, 4, false),
+    (Opcode::LDA, Mode::ABS, 4, false),
+    (Opcode::LDX, Mode::ABS, 4, false),
+    (Opcode::BBS2, Mode::ZPR, 5, false),
+    (Opcode::BCS, Mode::REL, 2, true),
+    (Opcode::LDA, Mode::ZIY, 5, true),
+    (Opcode::LDA, Mode::ZPI, 5, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::LDY, Mode::ZPX, 4, false),
+    (Opcode::LDA, Mode::ZPX, 4, false),
+    (Opcode::LDX, Mode::ZPY, 4, false),
+    (Opcode::SMB3, Mode::ZPG, 5, false),
+    (Opcode::CLV, Mode::IMP, 2, false),
+    (Opcode::LDA, Mode::ABY, 4, true),
+    (Opcode::TSX, Mode::IMP, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::LDY, Mode::ABX, 4, true),
+    (Opcode::LDA, Mode::ABX, 4, true),
+    (Opcode::LDX, Mode::ABY, 4, true),
+    (Opcode::BBS3, Mode::ZPR, 5, false),
+    (Opcode::CPY, Mode::IMM, 2, false),
+    (Opcode::CMP, Mode::ZIX, 6, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::CPY, Mode::ZPG, 3, false),
+    (Opcode::CMP, Mode::ZPG, 3, false),
+    (Opcode::DEC, Mode::ZPG, 5, false),
+    (Opcode::SMB4, Mode::ZPG, 5, false),
+    (Opcode::INY, Mode::IMP, 2, false),
+    (Opcode::CMP, Mode::IMM, 2, false),
+    (Opcode::DEX, Mode::IMP, 2, false),
+    (Opcode::WAI, Mode::IMP, 3, false),
+    (Opcode::CPY, Mode::ABS, 4, false),
+    (Opcode::CMP, Mode::ABS, 4, false),
+    (Opcode::DEC, Mode::ABS, 6, false),
+    (Opcode::BBS4, Mode::ZPR, 5, false),
+    (Opcode::BNE, Mode::REL, 2, true),
+    (Opcode::CMP, Mode::ZIY, 5, true),
+    (Opcode::CMP, Mode::ZPI, 5, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::NOP, Mode::ZPX, 4, false),
+    (Opcode::CMP, Mode::ZPX, 4, false),
+    (Opcode::DEC, Mode::ZPX, 6, false),
+    (Opcode::SMB5, Mode::ZPG, 5, false),
+    (Opcode::CLD, Mode::IMP, 2, false),
+    (Opcode::CMP, Mode::ABY, 4, true),
+    (Opcode::PHX, Mode::IMP, 3, false),
+    (Opcode::STP, Mode::IMP, 3, false),
+    (Opcode::NOP, Mode::ABS, 4, false),
+    (Opcode::CMP, Mode::ABX, 4, true),
+    (Opcode::DEC, Mode::ABX, 7, false),
+    (Opcode::BBS5, Mode::ZPR, 5, false),
+    (Opcode::CPX, Mode::IMM, 2, false),
+    (Opcode::SBC, Mode::ZIX, 6, false),
+    (Opcode::NOP, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::CPX, Mode::ZPG, 3, false),
+    (Opcode::SBC, Mode::ZPG, 3, false),
+    (Opcode::INC, Mode::ZPG, 5, false),
+    (Opcode::SMB6, Mode::ZPG, 5, false),
+    (Opcode::INX, Mode::IMP, 2, false),
+    (Opcode::SBC, Mode::IMM, 2, false),
+    (Opcode::NOP, Mode::IMP, 2, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::CPX, Mode::ABS, 4, false),
+    (Opcode::SBC, Mode::ABS, 4, false),
+    (Opcode::INC, Mode::ABS, 6, false),
+    (Opcode::BBS6, Mode::ZPR, 5, false),
+    (Opcode::BEQ, Mode::REL, 2, true),
+    (Opcode::SBC, Mode::ZIY, 5, true),
+    (Opcode::SBC, Mode::ZPI, 5, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::NOP, Mode::ZPX, 4, false),
+    (Opcode::SBC, Mode::ZPX, 4, false),
+    (Opcode::INC, Mode::ZPX, 6, false),
+    (Opcode::SMB7, Mode::ZPG, 5, false),
+    (Opcode::SED, Mode::IMP, 2, false),
+    (Opcode::SBC, Mode::ABY, 4, true),
+    (Opcode::PLX, Mode::IMP, 4, false),
+    (Opcode::NOP, Mode::IMP, 1, false),
+    (Opcode::NOP, Mode::ABS, 4, false),
     (Opcode::SBC, Mode::ABX, 4, true),
     (Opcode::INC, Mode::ABX, 7, false),
-    (Opcode::XXX, Mode::IMP, 7, false),
+    (Opcode::BBS7, Mode::ZPR, 5, false),
 ];
 
+/// Direction of a bus access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A single memory access made by the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+/// What happened during one call to `CPU6502::step_instruction`.
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    /// Address of the instruction, or the PC when an interrupt was taken
+    pub pc: u16,
+    /// Decoded instruction, or `None` for interrupt sequences and idle cycles
+    pub instruction: Option<Instruction>,
+    /// Raw operand (one or two bytes, little-endian)
+    pub operand: u16,
+    /// Effective address
+    pub op_addr: u16,
+    /// Cycles taken
+    pub cycles: u64,
+    pub branch_taken: bool,
+    pub page_crossed: bool,
+    /// Memory reads and writes in bus order, including dummy accesses
+    pub accesses: Vec<BusAccess>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+/// CPU state at the start of the instruction currently being executed
+/// in cycle-accurate mode, along with the interrupt decision made there.
+#[derive(Clone, Copy)]
+struct Checkpoint {
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u8,
+    p: Status,
+    instructions: usize,
+    instruction: Option<(u16, Instruction)>,
+    op_addr: u16,
+    waiting: bool,
+    stopped: bool,
+    irq_inhibit: bool,
+    interrupt: Option<Interrupt>,
+}
+
 pub struct CPU6502<T: IO> {
     pub mem: T,
     /// Program counter
@@ -510,6 +1096,49 @@ pub struct CPU6502<T: IO> {
     pub instruction: Option<(u16, Instruction)>,
     pub op_addr: u16,
     pub cycles_left: u8,
+
+    // Outcome of the current instruction
+    pub branch_taken: bool,
+    pub page_crossed: bool,
+
+    // Instruction set and behavior
+    pub variant: Variant,
+
+    // Waiting for an interrupt (65C02 `WAI`)
+    pub waiting: bool,
+
+    // Stopped until reset (65C02 `STP` or NMOS `JAM`)
+    pub stopped: bool,
+
+    // IRQ line, one bit per asserting source
+    irq_lines: u32,
+    // NMI line level and latched edge
+    nmi_line: bool,
+    nmi_prev: bool,
+    nmi_pending: bool,
+    // Value of the I flag at the last interrupt poll
+    irq_inhibit: bool,
+
+    // Perform one bus access per clock instead of a whole instruction
+    pub cycle_accurate: bool,
+
+    // Run bulk execution from pre-decoded blocks of code.
+    // Only writes are logged. Ignored in cycle-accurate mode.
+    pub block_cache: bool,
+    blocks: Option<Box<BlockCache<T>>>,
+    // Running an instruction from the block cache
+    cached: bool,
+    // Operand bytes of the instruction being run from the block cache, and where they start
+    prefetched: Option<(u16, [u8; 2])>,
+
+    // Bus accesses made by the current instruction
+    bus_log: Vec<BusAccess>,
+    // Number of accesses made so far while executing the instruction
+    bus_index: usize,
+    // In cycle-accurate mode, the index of the access to perform on the bus.
+    // Earlier accesses are replayed from the log and later ones are dropped.
+    replay: Option<usize>,
+    checkpoint: Option<Checkpoint>,
 }
 
 impl<T: IO> CPU6502<T> {
@@ -526,23 +1155,56 @@ impl<T: IO> CPU6502<T> {
             instruction: None,
             op_addr: 0,
             cycles_left: 0,
+            branch_taken: false,
+            page_crossed: false,
             instructions: 0,
+            variant: Variant::NMOS6502,
+            waiting: false,
+            stopped: false,
+            irq_lines: 0,
+            nmi_line: false,
+            nmi_prev: false,
+            nmi_pending: false,
+            irq_inhibit: true,
+            cycle_accurate: false,
+            block_cache: false,
+            blocks: None,
+            cached: false,
+            prefetched: None,
+            bus_log: Vec::with_capacity(8),
+            bus_index: 0,
+            replay: None,
+            checkpoint: None,
+        }
+    }
+
+    /// Instruction set for the selected CPU variant.
+    pub fn instruction_set(&self) -> &'static [Instruction; 256] {
+        match self.variant {
+            Variant::NMOS6502 => &INSTRUCTIONS,
+            Variant::WDC65C02 => &INSTRUCTIONS_65C02,
         }
     }
 
+    #[inline]
+    fn is_cmos(&self) -> bool {
+        self.variant == Variant::WDC65C02
+    }
+
     /// Reset the CPU to an initial good state.
     pub fn reset(&mut self) {
         // Get the starting program counter address.
         // This is stored at a predetermined location, 0xFFFC.
-        let pc_lo = self.read(0xFFFC) as u16;
-        let pc_hi = self.read(0xFFFD) as u16;
+        let pc_lo = self.mem.read(0xFFFC) as u16;
+        let pc_hi = self.mem.read(0xFFFD) as u16;
         let pc = (pc_hi << 8) | pc_lo;
 
         // Stack poiner counts *down* so start at 0XFF (255).
         let sp = 0xFF;
 
-        // Switch off status Status except for U (Unused) which is always on.
-        let status = Status::empty() | Status::U;
+        // Switch off status Status except for U (Unused) which is always on,
+        // and I, since interrupts are disabled until the program enables them.
+        let status = Status::empty() | Status::U | Status::I;
 
         self.pc = pc;
         self.a = 0;
@@ -554,138 +1216,621 @@ impl<T: IO> CPU6502<T> {
         self.instruction = None;
         self.op_addr = 0;
         self.cycles_left = 0;
+        self.waiting = false;
+        self.stopped = false;
+        self.nmi_pending = false;
+        self.irq_inhibit = true;
+        self.bus_log.clear();
+        self.replay = None;
+        self.checkpoint = None;
+    }
+
+    /// Assert or release the IRQ line on behalf of `source` (0-31).
+    ///
+    /// IRQ is level-sensitive and active as long as any source holds it.
+    /// Devices on the bus can also assert it through `IO::irq`.
+    pub fn set_irq(&mut self, source: u8, asserted: bool) {
+        let mask = 1 << (source & 0x1F);
+        if asserted {
+            self.irq_lines |= mask;
+        } else {
+            self.irq_lines &= !mask;
+        }
+    }
+
+    /// Drive the NMI line.
+    ///
+    /// NMI is edge-triggered: an interrupt is latched when the line goes
+    /// from released to asserted, and taken at the next instruction boundary.
+    /// Devices on the bus can also drive it through `IO::nmi`.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        self.nmi_line = asserted;
+    }
+
+    /// Whether the IRQ line is currently asserted by any source.
+    pub fn irq_asserted(&self) -> bool {
+        self.irq_lines != 0 || self.mem.irq()
     }
 
     pub fn execute(&mut self, instruction: Instruction) {
-        let (opcode, mode, cycles, can_cross_page_boundary) = instruction;
-        self.instruction = Some((self.pc, instruction));
+        self.execute_resolved_(
+            instruction,
+            Self::addressing_(instruction),
+            Self::handler_(instruction.0),
+        );
+    }
+
+    /// Execute `instruction` with its addressing mode and handler already
+    /// looked up, as the block cache does once per decoded instruction.
+    #[inline]
+    fn execute_resolved_(
+        &mut self,
+        instruction: Instruction,
+        addressing: Addressing<T>,
+        handler: Handler<T>,
+    ) {
+        let (_, _, cycles, can_cross_page_boundary) = instruction;
+        // The opcode has already been fetched, so it was at PC - 1.
+        self.instruction = Some((self.pc.wrapping_sub(1), instruction));
         self.instructions += 1;
         self.cycles_left = cycles - 1;
 
-        let crossed_page_boundary = match mode {
-            Mode::ABS => self.abs(),
-            Mode::ABX => self.abx(),
-            Mode::ABY => self.aby(),
-            Mode::IMM => self.imm(),
-            Mode::ZPX => self.zpx(),
-            Mode::ZPG => self.zpg(),
-            Mode::ZPY => self.zpy(),
-            Mode::IND => self.ind(),
-            Mode::REL => self.rel(),
-            Mode::ZIX => self.zix(),
-            Mode::ZIY => self.ziy(),
-            Mode::ACC => self.acc(),
-            Mode::IMP => self.imp(),
-        };
+        let crossed_page_boundary = addressing(self);
 
+        self.page_crossed = crossed_page_boundary;
         if crossed_page_boundary && can_cross_page_boundary {
             self.cycles_left += 1;
         }
 
+        handler(self);
+    }
+
+    /// The addressing mode of `instruction`, which sets `op_addr` and returns
+    /// whether indexing crossed a page.
+    fn addressing_(instruction: Instruction) -> Addressing<T> {
+        let (opcode, mode, cycles, can_cross_page_boundary) = instruction;
+        match mode {
+            // JSR interleaves fetching its operand with pushing the return address.
+            Mode::ABS if matches!(opcode, Opcode::JSR) => |_| false,
+            Mode::ABS => Self::abs,
+            Mode::ABX if can_cross_page_boundary => |cpu| cpu.abx(true),
+            Mode::ABX => |cpu| cpu.abx(false),
+            Mode::ABY if can_cross_page_boundary => |cpu| cpu.aby(true),
+            Mode::ABY => |cpu| cpu.aby(false),
+            Mode::IMM => Self::imm,
+            Mode::ZPX => Self::zpx,
+            Mode::ZPG => Self::zpg,
+            Mode::ZPY => Self::zpy,
+            Mode::IND => Self::ind,
+            Mode::REL => Self::rel,
+            Mode::ZIX => Self::zix,
+            Mode::ZIY if can_cross_page_boundary => |cpu| cpu.ziy(true),
+            Mode::ZIY => |cpu| cpu.ziy(false),
+            Mode::ACC => Self::acc,
+            // Single-cycle 65C02 NOPs don't touch the bus after the opcode fetch.
+            Mode::IMP if cycles == 1 => |_| false,
+            Mode::IMP => Self::imp,
+            Mode::ZPI => Self::zpi,
+            Mode::IAX => Self::iax,
+            Mode::ZPR => Self::zpg,
+        }
+    }
+
+    /// The function that carries out `opcode` once its address is known.
+    fn handler_(opcode: Opcode) -> Handler<T> {
         match opcode {
-            Opcode::ADC => self.adc(),
-            Opcode::AND => self.and(),
-            Opcode::ASL => self.asl(),
-            Opcode::ASL_A => self.asl_a(),
-            Opcode::BCC => self.bcc(),
-            Opcode::BCS => self.bcs(),
-            Opcode::BEQ => self.beq(),
-            Opcode::BIT => self.bit(),
-            Opcode::BMI => self.bmi(),
-            Opcode::BNE => self.bne(),
-            Opcode::BPL => self.bpl(),
-            Opcode::BRK => self.brk(),
-            Opcode::BVC => self.bvc(),
-            Opcode::BVS => self.bvs(),
-            Opcode::CLC => self.clc(),
-            Opcode::CLD => self.cld(),
-            Opcode::CLI => self.cli(),
-            Opcode::CLV => self.clv(),
-            Opcode::CMP => self.cmp(),
-            Opcode::CPX => self.cpx(),
-            Opcode::CPY => self.cpy(),
-            Opcode::DEC => self.dec(),
-            Opcode::DEX => self.dex(),
-            Opcode::DEY => self.dey(),
-            Opcode::EOR => self.eor(),
-            Opcode::INC => self.inc(),
-            Opcode::INX => self.inx(),
-            Opcode::INY => self.iny(),
-            Opcode::JMP => self.jmp(),
-            Opcode::JSR => self.jsr(),
-            Opcode::LDA => self.lda(),
-            Opcode::LDX => self.ldx(),
-            Opcode::LDY => self.ldy(),
-            Opcode::LSR => self.lsr(),
-            Opcode::LSR_A => self.lsr_a(),
-            Opcode::NOP => self.nop(),
-            Opcode::ORA => self.ora(),
-            Opcode::PHA => self.pha(),
-            Opcode::PHP => self.php(),
-            Opcode::PLA => self.pla(),
-            Opcode::PLP => self.plp(),
-            Opcode::ROL => self.rol(),
-            Opcode::ROL_A => self.rol_a(),
-            Opcode::ROR => self.ror(),
-            Opcode::ROR_A => self.ror_a(),
-            Opcode::RTI => self.rti(),
-            Opcode::RTS => self.rts(),
-            Opcode::SBC => self.sbc(),
-            Opcode::SEC => self.sec(),
-            Opcode::SED => self.sed(),
-            Opcode::SEI => self.sei(),
-            Opcode::STA => self.sta(),
-            Opcode::STX => self.stx(),
-            Opcode::STY => self.sty(),
-            Opcode::TAX => self.tax(),
-            Opcode::TAY => self.tay(),
-            Opcode::TSX => self.tsx(),
-            Opcode::TXA => self.txa(),
-            Opcode::TXS => self.txs(),
-            Opcode::TYA => self.tya(),
-            Opcode::XXX => self.xxx(),
-        };
+                Opcode::ADC => Self::adc,
+                Opcode::AND => Self::and,
+                Opcode::ASL => Self::asl,
+                Opcode::ASL_A => Self::asl_a,
+                Opcode::BCC => Self::bcc,
+                Opcode::BCS => Self::bcs,
+                Opcode::BEQ => Self::beq,
+                Opcode::BIT => Self::bit,
+                Opcode::BMI => Self::bmi,
+                Opcode::BNE => Self::bne,
+                Opcode::BPL => Self::bpl,
+                Opcode::BRK => Self::brk,
+                Opcode::BVC => Self::bvc,
+                Opcode::BVS => Self::bvs,
+                Opcode::CLC => Self::clc,
+                Opcode::CLD => Self::cld,
+                Opcode::CLI => Self::cli,
+                Opcode::CLV => Self::clv,
+                Opcode::CMP => Self::cmp,
+                Opcode::CPX => Self::cpx,
+                Opcode::CPY => Self::cpy,
+                Opcode::DEC => Self::dec,
+                Opcode::DEX => Self::dex,
+                Opcode::DEY => Self::dey,
+                Opcode::EOR => Self::eor,
+                Opcode::INC => Self::inc,
+                Opcode::INX => Self::inx,
+                Opcode::INY => Self::iny,
+                Opcode::JMP => Self::jmp,
+                Opcode::JSR => Self::jsr,
+                Opcode::LDA => Self::lda,
+                Opcode::LDX => Self::ldx,
+                Opcode::LDY => Self::ldy,
+                Opcode::LSR => Self::lsr,
+                Opcode::LSR_A => Self::lsr_a,
+                Opcode::NOP => Self::nop,
+                Opcode::ORA => Self::ora,
+                Opcode::PHA => Self::pha,
+                Opcode::PHP => Self::php,
+                Opcode::PLA => Self::pla,
+                Opcode::PLP => Self::plp,
+                Opcode::ROL => Self::rol,
+                Opcode::ROL_A => Self::rol_a,
+                Opcode::ROR => Self::ror,
+                Opcode::ROR_A => Self::ror_a,
+                Opcode::RTI => Self::rti,
+                Opcode::RTS => Self::rts,
+                Opcode::SBC => Self::sbc,
+                Opcode::SEC => Self::sec,
+                Opcode::SED => Self::sed,
+                Opcode::SEI => Self::sei,
+                Opcode::STA => Self::sta,
+                Opcode::STX => Self::stx,
+                Opcode::STY => Self::sty,
+                Opcode::TAX => Self::tax,
+                Opcode::TAY => Self::tay,
+                Opcode::TSX => Self::tsx,
+                Opcode::TXA => Self::txa,
+                Opcode::TXS => Self::txs,
+                Opcode::TYA => Self::tya,
+                Opcode::AHX => Self::ahx,
+                Opcode::ALR => Self::alr,
+                Opcode::ANC => Self::anc,
+                Opcode::ARR => Self::arr,
+                Opcode::DCP => Self::dcp,
+                Opcode::ISC => Self::isc,
+                Opcode::JAM => Self::jam,
+                Opcode::LAS => Self::las,
+                Opcode::LAX => Self::lax,
+                Opcode::LXA => Self::lxa,
+                Opcode::RLA => Self::rla,
+                Opcode::RRA => Self::rra,
+                Opcode::SAX => Self::sax,
+                Opcode::SBX => Self::sbx,
+                Opcode::SHX => Self::shx,
+                Opcode::SHY => Self::shy,
+                Opcode::SLO => Self::slo,
+                Opcode::SRE => Self::sre,
+                Opcode::TAS => Self::tas,
+                Opcode::XAA => Self::xaa,
+                Opcode::BRA => Self::bra,
+                Opcode::DEC_A => Self::dec_a,
+                Opcode::INC_A => Self::inc_a,
+                Opcode::PHX => Self::phx,
+                Opcode::PHY => Self::phy,
+                Opcode::PLX => Self::plx,
+                Opcode::PLY => Self::ply,
+                Opcode::STP => Self::stp,
+                Opcode::STZ => Self::stz,
+                Opcode::TRB => Self::trb,
+                Opcode::TSB => Self::tsb,
+                Opcode::WAI => Self::wai,
+                Opcode::BBR0 => |cpu| cpu.bbr_(0),
+                Opcode::BBR1 => |cpu| cpu.bbr_(1),
+                Opcode::BBR2 => |cpu| cpu.bbr_(2),
+                Opcode::BBR3 => |cpu| cpu.bbr_(3),
+                Opcode::BBR4 => |cpu| cpu.bbr_(4),
+                Opcode::BBR5 => |cpu| cpu.bbr_(5),
+                Opcode::BBR6 => |cpu| cpu.bbr_(6),
+                Opcode::BBR7 => |cpu| cpu.bbr_(7),
+                Opcode::BBS0 => |cpu| cpu.bbs_(0),
+                Opcode::BBS1 => |cpu| cpu.bbs_(1),
+                Opcode::BBS2 => |cpu| cpu.bbs_(2),
+                Opcode::BBS3 => |cpu| cpu.bbs_(3),
+                Opcode::BBS4 => |cpu| cpu.bbs_(4),
+                Opcode::BBS5 => |cpu| cpu.bbs_(5),
+                Opcode::BBS6 => |cpu| cpu.bbs_(6),
+                Opcode::BBS7 => |cpu| cpu.bbs_(7),
+                Opcode::RMB0 => |cpu| cpu.rmb_(0),
+                Opcode::RMB1 => |cpu| cpu.rmb_(1),
+                Opcode::RMB2 => |cpu| cpu.rmb_(2),
+                Opcode::RMB3 => |cpu| cpu.rmb_(3),
+                Opcode::RMB4 => |cpu| cpu.rmb_(4),
+                Opcode::RMB5 => |cpu| cpu.rmb_(5),
+                Opcode::RMB6 => |cpu| cpu.rmb_(6),
+                Opcode::RMB7 => |cpu| cpu.rmb_(7),
+                Opcode::SMB0 => |cpu| cpu.smb_(0),
+                Opcode::SMB1 => |cpu| cpu.smb_(1),
+                Opcode::SMB2 => |cpu| cpu.smb_(2),
+                Opcode::SMB3 => |cpu| cpu.smb_(3),
+                Opcode::SMB4 => |cpu| cpu.smb_(4),
+                Opcode::SMB5 => |cpu| cpu.smb_(5),
+                Opcode::SMB6 => |cpu| cpu.smb_(6),
+                Opcode::SMB7 => |cpu| cpu.smb_(7),
+        }
     }
 
+    /// Advance the CPU by one cycle.
+    ///
+    /// By default the whole instruction runs on its first cycle and the
+    /// remaining cycles are counted down. With `cycle_accurate` set, each
+    /// cycle performs exactly one of the instruction's bus accesses, and
+    /// registers are updated once the instruction completes.
     pub fn clock(&mut self) {
         self.cycles += 1;
+        self.mem.tick(1);
+
+        if self.cycle_accurate {
+            self.clock_cycle_();
+            return;
+        }
 
         if self.cycles_left > 0 {
             self.cycles_left -= 1;
             return;
         }
 
-        let opcode = self.pop_u8();
-        let instruction = INSTRUCTIONS[opcode as usize];
-        self.execute(instruction);
-
-        // self.cycles_left = 0;
+        // Interrupts are polled between instructions.
+        let interrupt = self.poll_interrupts();
+        self.bus_log.clear();
+        self.bus_index = 0;
+        self.step_(interrupt);
 
         if (DEBUG) {
             self.print_state();
         }
     }
 
-    pub fn cycles(&self) -> u64 {
-        self.cycles
-    }
+    /// Run the CPU until the next instruction boundary and report what happened.
+    ///
+    /// An instruction already in flight is finished first. A step either runs
+    /// one instruction, an interrupt sequence, or a single idle cycle (`WAI`/`STP`).
+    pub fn step_instruction(&mut self) -> StepResult {
+        while self.cycles_left > 0 {
+            self.clock();
+        }
 
-    pub fn halted(&self) -> bool {
-        self.p.contains(Status::B)
-    }
+        let start_pc = self.pc;
+        let start_cycles = self.cycles;
+        let start_instructions = self.instructions;
 
-    pub fn print_state(&mut self) {
-        let color_flag = |f: u8| {
-            if f == 1 {
-                f.to_string().green()
-            } else {
-                ColoredString::from(f.to_string().as_str())
+        self.clock();
+        while self.cycles_left > 0 {
+            self.clock();
+        }
+
+        let (pc, instruction) = match self.instruction {
+            Some((addr, instruction)) if self.instructions != start_instructions => {
+                (addr, Some(instruction))
             }
+            _ => (start_pc, None),
         };
 
-        let f: [u8; 8] = [
-            if self.p.contains(Status::N) { 1 } else { 0 },
-            if self.p.contains(Status::V) { 1 } else { 0 },
+        // Operand bytes are the reads that follow the opcode.
+        let operand_byte = |offset: u16| {
+            self.bus_log
+                .iter()
+                .find(|access| {
+                    access.kind == AccessKind::Read && access.addr == pc.wrapping_add(offset)
+                })
+                .map_or(0, |access| access.value as u16)
+        };
+        let operand = match instruction {
+            Some((_, mode, _, _)) if mode.size() == 3 => operand_byte(1) | (operand_byte(2) << 8),
+            Some((_, mode, _, _)) if mode.size() == 2 => operand_byte(1),
+            _ => 0,
+        };
+
+        StepResult {
+            pc,
+            instruction,
+            operand,
+            op_addr: self.op_addr,
+            cycles: self.cycles - start_cycles,
+            branch_taken: self.branch_taken,
+            page_crossed: self.page_crossed,
+            accesses: self.bus_log.clone(),
+        }
+    }
+
+    /// Run until at least `cycles` more cycles have passed.
+    ///
+    /// Stops early if the CPU jams or executes `STP`.
+    pub fn run_for_cycles(&mut self, cycles: u64) -> HaltReason {
+        let limit = self.cycles + cycles;
+        self.run_until(|cpu| {
+            if cpu.cycles >= limit {
+                Some(HaltReason::CycleLimit(cpu.cycles))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Run `instructions` more instructions.
+    ///
+    /// Stops early if the CPU jams or executes `STP`.
+    pub fn run_for_instructions(&mut self, instructions: usize) -> HaltReason {
+        let limit = self.instructions + instructions;
+        self.run_until(|cpu| {
+            if cpu.instructions >= limit {
+                Some(HaltReason::InstructionLimit(cpu.instructions))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Run whole instructions until `halt` returns a reason to stop.
+    ///
+    /// `halt` is checked at every instruction boundary. A jammed or stopped
+    /// CPU can't make progress, so that always ends the run. Infinite loops
+    /// are left to `halt`, since firmware may idle in one waiting for an interrupt.
+    pub fn run_until<F>(&mut self, mut halt: F) -> HaltReason
+    where
+        F: FnMut(&Self) -> Option<HaltReason>,
+    {
+        loop {
+            self.dispatch_instruction();
+
+            if self.stopped {
+                if let Some(reason) = self.halt_reason() {
+                    return reason;
+                }
+            }
+            if let Some(reason) = halt(self) {
+                return reason;
+            }
+        }
+    }
+
+    /// Run the next instruction (or interrupt sequence) in one go, instead of
+    /// clocking through it a cycle at a time. An instruction already in flight
+    /// is finished instead.
+    pub(crate) fn dispatch_instruction(&mut self) {
+        if self.cycle_accurate {
+            self.clock();
+            while self.cycles_left > 0 {
+                self.clock();
+            }
+            return;
+        }
+
+        if self.cycles_left == 0 {
+            self.cycles += 1;
+            self.mem.tick(1);
+            let interrupt = self.poll_interrupts();
+            self.bus_log.clear();
+            self.bus_index = 0;
+
+            if self.block_cache && interrupt.is_none() && !self.waiting && !self.stopped {
+                self.step_cached_();
+            } else {
+                self.step_(interrupt);
+            }
+        }
+
+        // Devices catch up on the rest of the instruction in one go
+        if self.cycles_left > 0 {
+            self.cycles += self.cycles_left as u64;
+            self.mem.tick(self.cycles_left as u32);
+            self.cycles_left = 0;
+        }
+    }
+
+    /// Run the next instruction from the block cache.
+    ///
+    /// The opcode and operand bytes come from the cache instead of the bus,
+    /// and only writes are added to the bus log. Every other access, dummy
+    /// accesses included, is the same as in the reference interpreter.
+    fn step_cached_(&mut self) {
+        let variant = self.variant;
+        let instruction_set = self.instruction_set();
+        let map_generation = self.mem.map_generation();
+        let blocks = self
+            .blocks
+            .get_or_insert_with(|| Box::new(BlockCache::new(variant, map_generation)));
+        let op = blocks.fetch(self.pc, &self.mem, variant, instruction_set);
+
+        self.branch_taken = false;
+        self.page_crossed = false;
+
+        self.pc = self.pc.wrapping_add(1);
+        self.prefetched = Some((self.pc, op.operand));
+        // The opcode fetch
+        self.bus_index = 1;
+        self.cached = true;
+        self.execute_(op.instruction, op.addressing, op.handler);
+        self.cached = false;
+        self.prefetched = None;
+    }
+
+    /// Drop all cached blocks.
+    ///
+    /// Writes made by the CPU are tracked automatically. Call this after
+    /// changing memory behind the CPU's back, e.g. loading a program.
+    pub fn flush_block_cache(&mut self) {
+        if let Some(blocks) = &mut self.blocks {
+            blocks.flush();
+        }
+    }
+
+    /// Bus accesses made by the current (or last completed) instruction.
+    pub fn bus_accesses(&self) -> &[BusAccess] {
+        &self.bus_log
+    }
+
+    /// Run one cycle of the current instruction.
+    ///
+    /// The instruction is re-run from its checkpoint every cycle. Accesses
+    /// already made are replayed from the log, the next one goes to the bus,
+    /// and the rest are dropped. Once an instruction runs without dropping
+    /// any accesses, it is complete.
+    fn clock_cycle_(&mut self) {
+        let checkpoint = match self.checkpoint.take() {
+            Some(checkpoint) => {
+                self.restore_(checkpoint);
+                checkpoint
+            }
+            None => {
+                // Interrupts are polled between instructions.
+                let interrupt = self.poll_interrupts();
+                self.bus_log.clear();
+                self.checkpoint_(interrupt)
+            }
+        };
+
+        self.bus_index = 0;
+        self.replay = Some(self.bus_log.len());
+        self.step_(checkpoint.interrupt);
+        self.replay = None;
+
+        if self.bus_index > self.bus_log.len() {
+            self.checkpoint = Some(checkpoint);
+            self.cycles_left = 1;
+        } else {
+            self.cycles_left = 0;
+
+            if DEBUG {
+                self.print_state();
+            }
+        }
+    }
+
+    fn checkpoint_(&self, interrupt: Option<Interrupt>) -> Checkpoint {
+        Checkpoint {
+            pc: self.pc,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            sp: self.sp,
+            p: self.p,
+            instructions: self.instructions,
+            instruction: self.instruction,
+            op_addr: self.op_addr,
+            waiting: self.waiting,
+            stopped: self.stopped,
+            irq_inhibit: self.irq_inhibit,
+            interrupt,
+        }
+    }
+
+    fn restore_(&mut self, checkpoint: Checkpoint) {
+        self.pc = checkpoint.pc;
+        self.a = checkpoint.a;
+        self.x = checkpoint.x;
+        self.y = checkpoint.y;
+        self.sp = checkpoint.sp;
+        self.p = checkpoint.p;
+        self.instructions = checkpoint.instructions;
+        self.instruction = checkpoint.instruction;
+        self.op_addr = checkpoint.op_addr;
+        self.waiting = checkpoint.waiting;
+        self.stopped = checkpoint.stopped;
+        self.irq_inhibit = checkpoint.irq_inhibit;
+    }
+
+    /// Run an interrupt sequence or the next instruction.
+    fn step_(&mut self, interrupt: Option<Interrupt>) {
+        self.branch_taken = false;
+        self.page_crossed = false;
+
+        match interrupt {
+            Some(Interrupt::Nmi) => return self.nmi(),
+            Some(Interrupt::Irq) => return self.irq(),
+            None => {}
+        }
+
+        // WAI and STP idle the processor without fetching instructions.
+        if self.waiting || self.stopped {
+            return;
+        }
+
+        let opcode = self.pop_u8();
+        let instruction = self.instruction_set()[opcode as usize];
+        self.execute_(
+            instruction,
+            Self::addressing_(instruction),
+            Self::handler_(instruction.0),
+        );
+
+        // self.cycles_left = 0;
+    }
+
+    /// Execute an instruction whose opcode has been fetched.
+    #[inline]
+    fn execute_(&mut self, instruction: Instruction, addressing: Addressing<T>, handler: Handler<T>) {
+        let i_flag = self.p.contains(Status::I);
+        self.execute_resolved_(instruction, addressing, handler);
+
+        // CLI, SEI and PLP change the I flag after the interrupt lines are polled,
+        // so the new value only takes effect after the following instruction.
+        self.irq_inhibit = match instruction.0 {
+            Opcode::CLI | Opcode::SEI | Opcode::PLP => i_flag,
+            _ => self.p.contains(Status::I),
+        };
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halt_reason().is_some()
+    }
+
+    /// Why the CPU can't make any further progress, if it can't.
+    ///
+    /// Call this at an instruction boundary (`cycles_left == 0`).
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        let (addr, (opcode, ..)) = self.instruction?;
+
+        if self.stopped {
+            return match opcode {
+                Opcode::JAM => Some(HaltReason::Jam(addr)),
+                _ => Some(HaltReason::Stop(addr)),
+            };
+        }
+
+        // Nothing but an interrupt can leave an instruction that jumps to itself.
+        // With IRQs enabled that's the usual idle loop of interrupt-driven
+        // firmware, so it only counts as stuck with them masked.
+        if addr == self.pc
+            && self.p.contains(Status::I)
+            && !self.nmi_due_()
+            && matches!(
+                opcode,
+                Opcode::JMP
+                    | Opcode::BRA
+                    | Opcode::BCC
+                    | Opcode::BCS
+                    | Opcode::BEQ
+                    | Opcode::BMI
+                    | Opcode::BNE
+                    | Opcode::BPL
+                    | Opcode::BVC
+                    | Opcode::BVS
+            )
+        {
+            return Some(HaltReason::InfiniteLoop(addr));
+        }
+
+        None
+    }
+
+    /// Whether an NMI will be taken before the next instruction.
+    fn nmi_due_(&self) -> bool {
+        self.nmi_pending || ((self.nmi_line || self.mem.nmi()) && !self.nmi_prev)
+    }
+
+    pub fn print_state(&mut self) {
+        let color_flag = |f: u8| {
+            if f == 1 {
+                f.to_string().green()
+            } else {
+                ColoredString::from(f.to_string().as_str())
+            }
+        };
+
+        let f: [u8; 8] = [
+            if self.p.contains(Status::N) { 1 } else { 0 },
+            if self.p.contains(Status::V) { 1 } else { 0 },
             if self.p.contains(Status::U) { 1 } else { 0 },
             if self.p.contains(Status::B) { 1 } else { 0 },
             if self.p.contains(Status::D) { 1 } else { 0 },
@@ -722,7 +1867,7 @@ impl<T: IO> CPU6502<T> {
         if let Some(instruction) = self.instruction {
             let formatted_operand = match instruction.1 .1 {
                 Mode::IMP => "".to_string(),
-                Mode::IMM => format!("#${:02X}", self.read(self.op_addr)),
+                Mode::IMM => format!("#${:02X}", self.mem.read(self.op_addr)),
                 Mode::ACC => "A".to_string(),
                 Mode::ABS => format!("${:04X}", self.op_addr),
                 Mode::ABX => format!("${:04X},X", self.op_addr),
@@ -734,6 +1879,9 @@ impl<T: IO> CPU6502<T> {
                 Mode::ZIY => format!("(${:02X},Y)", self.op_addr),
                 Mode::IND => format!("(${:04X})", self.op_addr),
                 Mode::REL => format!("${:04X}", self.op_addr),
+                Mode::ZPI => format!("(${:02X})", self.op_addr),
+                Mode::IAX => format!("(${:04X},X)", self.op_addr),
+                Mode::ZPR => format!("${:04X}", self.op_addr),
             };
             format!("{:#?} {}", instruction.1, &formatted_operand)
         } else {
@@ -741,19 +1889,144 @@ impl<T: IO> CPU6502<T> {
         }
     }
 
+    /// Trace the instruction at PC, before it is executed, in the layout of nestest.log:
+    ///
+    /// ```text
+    /// C72E  85 00     STA $00 = 00                    A:00 X:00 Y:00 P:26 SP:FB CYC:30
+    /// ```
+    ///
+    /// Undocumented NMOS opcodes are marked with `*`. There is no PPU, so the
+    /// `PPU:` column is left out.
+    pub fn trace_line(&self) -> String {
+        let pc = self.pc;
+        let opcode = self.mem.peek(pc);
+        let (op, mode, _, _) = self.instruction_set()[opcode as usize];
+
+        let size = mode.size();
+        let bytes = (0..size)
+            .map(|i| format!("{:02X}", self.mem.peek(pc.wrapping_add(i))))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let op8 = self.mem.peek(pc.wrapping_add(1));
+        let op16 = ((self.mem.peek(pc.wrapping_add(2)) as u16) << 8) | op8 as u16;
+        let next_pc = pc.wrapping_add(size);
+
+        let read16_zp = |cpu: &Self, ptr: u8| {
+            let lo = cpu.mem.peek(ptr as u16) as u16;
+            let hi = cpu.mem.peek(ptr.wrapping_add(1) as u16) as u16;
+            (hi << 8) | lo
+        };
+
+        let jump = matches!(op, Opcode::JMP | Opcode::JSR);
+        let operand = match mode {
+            Mode::IMP => "".to_string(),
+            Mode::ACC => "A".to_string(),
+            Mode::IMM => format!("#${:02X}", op8),
+            Mode::ABS if jump => format!("${:04X}", op16),
+            Mode::ABS => format!("${:04X} = {:02X}", op16, self.mem.peek(op16)),
+            Mode::ABX | Mode::ABY => {
+                let (index, name) = match mode {
+                    Mode::ABX => (self.x, "X"),
+                    _ => (self.y, "Y"),
+                };
+                let addr = op16.wrapping_add(index as u16);
+                format!("${:04X},{} @ {:04X} = {:02X}", op16, name, addr, self.mem.peek(addr))
+            }
+            Mode::ZPG => format!("${:02X} = {:02X}", op8, self.mem.peek(op8 as u16)),
+            Mode::ZPX | Mode::ZPY => {
+                let (index, name) = match mode {
+                    Mode::ZPX => (self.x, "X"),
+                    _ => (self.y, "Y"),
+                };
+                let addr = op8.wrapping_add(index);
+                format!("${:02X},{} @ {:02X} = {:02X}", op8, name, addr, self.mem.peek(addr as u16))
+            }
+            Mode::IND => {
+                let hi_ptr = if self.is_cmos() {
+                    op16.wrapping_add(1)
+                } else {
+                    (op16 & 0xFF00) | (op16.wrapping_add(1) & 0x00FF)
+                };
+                let addr = ((self.mem.peek(hi_ptr) as u16) << 8) | self.mem.peek(op16) as u16;
+                format!("(${:04X}) = {:04X}", op16, addr)
+            }
+            Mode::ZIX => {
+                let ptr = op8.wrapping_add(self.x);
+                let addr = read16_zp(self, ptr);
+                format!("(${:02X},X) @ {:02X} = {:04X} = {:02X}", op8, ptr, addr, self.mem.peek(addr))
+            }
+            Mode::ZIY => {
+                let base = read16_zp(self, op8);
+                let addr = base.wrapping_add(self.y as u16);
+                format!("(${:02X}),Y = {:04X} @ {:04X} = {:02X}", op8, base, addr, self.mem.peek(addr))
+            }
+            Mode::ZPI => {
+                let addr = read16_zp(self, op8);
+                format!("(${:02X}) = {:04X} = {:02X}", op8, addr, self.mem.peek(addr))
+            }
+            Mode::IAX => {
+                let ptr = op16.wrapping_add(self.x as u16);
+                let addr = ((self.mem.peek(ptr.wrapping_add(1)) as u16) << 8) | self.mem.peek(ptr) as u16;
+                format!("(${:04X},X) @ {:04X} = {:04X}", op16, ptr, addr)
+            }
+            Mode::REL => {
+                let target = next_pc.wrapping_add(op8 as i8 as u16);
+                format!("${:04X}", target)
+            }
+            Mode::ZPR => {
+                let target = next_pc.wrapping_add((op16 >> 8) as u8 as i8 as u16);
+                format!("${:02X} = {:02X},${:04X}", op8, self.mem.peek(op8 as u16), target)
+            }
+        };
+
+        let undocumented = !self.is_cmos()
+            && match op {
+                Opcode::NOP => opcode != 0xEA,
+                Opcode::SBC => opcode == 0xEB,
+                _ => op.is_undocumented(),
+            };
+
+        // nestest.log spells ISC as ISB
+        let mnemonic = match op {
+            Opcode::ISC => "ISB".to_string(),
+            _ => op.to_string(),
+        };
+
+        let disassembly = format!("{} {}", mnemonic, operand);
+
+        format!(
+            "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            pc,
+            bytes,
+            if undocumented { '*' } else { ' ' },
+            disassembly.trim_end(),
+            self.a,
+            self.x,
+            self.y,
+            self.p.bits(),
+            self.sp,
+            self.cycles
+        )
+    }
+
     // Addresing Modes
     //
     //
 
     /// Implied
+    ///
+    /// The byte after the opcode is read and discarded.
     #[inline]
     fn imp(&mut self) -> bool {
+        self.read(self.pc);
         false
     }
 
     // Accumulator
     #[inline]
     fn acc(&mut self) -> bool {
+        self.read(self.pc);
         false
     }
 
@@ -768,7 +2041,7 @@ impl<T: IO> CPU6502<T> {
     #[inline]
     fn imm(&mut self) -> bool {
         let addr = self.pc;
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         self.op_addr = addr;
         false
     }
@@ -778,8 +2051,21 @@ impl<T: IO> CPU6502<T> {
     fn ind(&mut self) -> bool {
         let addr_ptr = self.pop_u16();
 
+        // The NMOS 6502 does not carry into the high byte of the pointer,
+        // so JMP ($xxFF) reads its high byte from $xx00.
+        // The 65C02 fixed this at the cost of an extra cycle (counted in its instruction table).
+        let hi_ptr = if self.is_cmos() {
+            addr_ptr.wrapping_add(1)
+        } else {
+            (addr_ptr & 0xFF00) | (addr_ptr.wrapping_add(1) & 0x00FF)
+        };
+
+        if self.is_cmos() {
+            self.read(self.pc.wrapping_sub(1));
+        }
+
         let lo = self.read(addr_ptr) as u16;
-        let hi = self.read(addr_ptr + 1) as u16;
+        let hi = self.read(hi_ptr) as u16;
         let addr = (hi << 8) | lo;
 
         self.op_addr = addr;
@@ -800,6 +2086,9 @@ impl<T: IO> CPU6502<T> {
     #[inline]
     fn zpx(&mut self) -> bool {
         let lo = self.pop_u8();
+        // The unindexed address is read while X is added
+        self.read(lo as u16);
+
         // No carry:
         // Even though the final value is 16 bits,
         // wrap around if the X offset + lo bit > 0xFF.
@@ -814,6 +2103,9 @@ impl<T: IO> CPU6502<T> {
     #[inline]
     fn zpy(&mut self) -> bool {
         let lo = self.pop_u8();
+        // The unindexed address is read while Y is added
+        self.read(lo as u16);
+
         // No carry:
         // Even though the final value is 16 bits,
         // wrap around if the Y offset + lo bit > 0xFF.
@@ -825,28 +2117,50 @@ impl<T: IO> CPU6502<T> {
     }
 
     /// Absolute, X-Indexed
-    fn abx(&mut self) -> bool {
+    fn abx(&mut self, can_cross_page_boundary: bool) -> bool {
         self.abs();
         let abs_addr = self.op_addr;
 
-        // Carry offset
-        let addr = abs_addr + self.x as u16;
+        // Carry offset, wrapping around from $FFFF to $0000
+        let addr = abs_addr.wrapping_add(self.x as u16);
 
         self.op_addr = addr;
-        self.crossed_page_boundary(abs_addr, addr)
+        self.fix_page_(abs_addr, can_cross_page_boundary)
     }
 
     /// Absolute, Y-Indexed
     #[inline]
-    fn aby(&mut self) -> bool {
+    fn aby(&mut self, can_cross_page_boundary: bool) -> bool {
         self.abs();
         let abs_addr = self.op_addr;
 
-        // Carry offset
-        let addr = abs_addr + self.y as u16;
+        // Carry offset, wrapping around from $FFFF to $0000
+        let addr = abs_addr.wrapping_add(self.y as u16);
 
         self.op_addr = addr;
-        self.crossed_page_boundary(abs_addr, addr)
+        self.fix_page_(abs_addr, can_cross_page_boundary)
+    }
+
+    /// Indexed addressing adds the index to the low byte first,
+    /// then spends a cycle fixing up the high byte if there was a carry.
+    /// During that cycle the NMOS 6502 reads from the unfixed address,
+    /// and the 65C02 re-reads the last operand byte.
+    ///
+    /// Read instructions skip the extra cycle when no page boundary was crossed.
+    /// Writes and read-modify-writes always take it.
+    #[inline]
+    fn fix_page_(&mut self, base_addr: u16, can_cross_page_boundary: bool) -> bool {
+        let crossed = self.crossed_page_boundary(base_addr, self.op_addr);
+
+        if crossed || !can_cross_page_boundary {
+            if self.is_cmos() {
+                self.read(self.pc.wrapping_sub(1));
+            } else {
+                self.read((base_addr & 0xFF00) | (self.op_addr & 0x00FF));
+            }
+        }
+
+        crossed
     }
 
     /// Relative
@@ -882,11 +2196,15 @@ impl<T: IO> CPU6502<T> {
     #[inline]
     fn zix(&mut self) -> bool {
         let ptr_lo = self.pop_u8();
-        let ptr_lo_idx = ptr_lo.wrapping_add(self.x);
-        let ptr = 0x0000 | (ptr_lo_idx as u16);
+        // The unindexed pointer is read while X is added
+        self.read(ptr_lo as u16);
 
-        let lo = self.read(ptr) as u16;
-        let hi = self.read(ptr + 1) as u16;
+        let ptr = ptr_lo.wrapping_add(self.x);
+
+        // The pointer never leaves zero page:
+        // a pointer at $FF takes its high byte from $00.
+        let lo = self.read(ptr as u16) as u16;
+        let hi = self.read(ptr.wrapping_add(1) as u16) as u16;
         let addr = (hi << 8) | lo;
 
         self.op_addr = addr;
@@ -898,17 +2216,48 @@ impl<T: IO> CPU6502<T> {
     /// Operand is zero page address.
     /// Absolute address is word in (OP, OP + 1) offset by Y.
     #[inline]
-    fn ziy(&mut self) -> bool {
-        self.zpg();
-        let ptr = self.op_addr;
+    fn ziy(&mut self, can_cross_page_boundary: bool) -> bool {
+        let ptr = self.pop_u8();
 
-        let lo = self.read(ptr) as u16;
-        let hi = self.read(ptr + 1) as u16;
+        // The pointer never leaves zero page:
+        // a pointer at $FF takes its high byte from $00.
+        let lo = self.read(ptr as u16) as u16;
+        let hi = self.read(ptr.wrapping_add(1) as u16) as u16;
         let abs_addr = (hi << 8) | lo;
-        let addr = abs_addr + self.y as u16;
+        let addr = abs_addr.wrapping_add(self.y as u16);
         self.op_addr = addr;
 
-        self.crossed_page_boundary(abs_addr, addr)
+        self.fix_page_(abs_addr, can_cross_page_boundary)
+    }
+
+    /// Zero Page Indirect (65C02)
+    ///
+    /// Operand is zero page address.
+    /// Absolute address is word in (OP, OP + 1).
+    #[inline]
+    fn zpi(&mut self) -> bool {
+        let ptr = self.pop_u8();
+
+        // The pointer wraps within zero page, as with the other indirect modes.
+        let lo = self.read(ptr as u16) as u16;
+        let hi = self.read(ptr.wrapping_add(1) as u16) as u16;
+        self.op_addr = (hi << 8) | lo;
+        false
+    }
+
+    /// Absolute Indexed Indirect (65C02)
+    ///
+    /// Operand is absolute address.
+    /// Absolute address is word in (OP + X, OP + X + 1).
+    #[inline]
+    fn iax(&mut self) -> bool {
+        let addr_ptr = self.pop_u16().wrapping_add(self.x as u16);
+        self.read(self.pc.wrapping_sub(1));
+
+        let lo = self.read(addr_ptr) as u16;
+        let hi = self.read(addr_ptr.wrapping_add(1)) as u16;
+        self.op_addr = (hi << 8) | lo;
+        false
     }
 
     //
@@ -917,17 +2266,16 @@ impl<T: IO> CPU6502<T> {
     //
     //
 
-    /// XXX - Illegal Instruction
-    ///
-    fn xxx(&mut self) {
-        dbg!("XXX - Illegal Instruction: ({})", self.instruction);
-    }
-
     /// ADC - Add with Carry
     ///
     fn adc(&mut self) {
-        let acc = self.a;
         let op = self.read(self.op_addr);
+        self.adc_(op);
+    }
+
+    #[inline]
+    fn adc_(&mut self, op: u8) {
+        let acc = self.a;
 
         if !self.p.contains(Status::D) {
             self.add_a_(acc, op);
@@ -948,6 +2296,7 @@ impl<T: IO> CPU6502<T> {
     ///
     fn asl(&mut self) {
         let byte = self.read(self.op_addr);
+        self.rmw_dummy_(byte);
 
         let asl_value = self.asl_(byte);
         self.write(self.op_addr, asl_value);
@@ -990,6 +2339,7 @@ impl<T: IO> CPU6502<T> {
     ///
     fn lsr(&mut self) {
         let byte = self.read(self.op_addr);
+        self.rmw_dummy_(byte);
 
         let lsr_value = self.lsr_(byte);
         self.write(self.op_addr, lsr_value);
@@ -1016,6 +2366,7 @@ impl<T: IO> CPU6502<T> {
     ///
     fn rol(&mut self) {
         let byte = self.read(self.op_addr);
+        self.rmw_dummy_(byte);
 
         let rol_value = self.rol_(byte);
         self.write(self.op_addr, rol_value);
@@ -1055,6 +2406,7 @@ impl<T: IO> CPU6502<T> {
     ///
     fn ror(&mut self) {
         let byte = self.read(self.op_addr);
+        self.rmw_dummy_(byte);
 
         let ror_value = self.ror_(byte);
         self.write(self.op_addr, ror_value);
@@ -1092,21 +2444,20 @@ impl<T: IO> CPU6502<T> {
     /// SBC - Subtract with Carry
     ///
     fn sbc(&mut self) {
+        let op = self.read(self.op_addr);
+        self.sbc_(op);
+    }
+
+    #[inline]
+    fn sbc_(&mut self, op: u8) {
         let acc = self.a;
 
         if !self.p.contains(Status::D) {
             // One's complement
             // Don't add 1 since we're adding the carry bit.
-            let op = self.read(self.op_addr) ^ 0xFF;
-            self.add_a_(acc, op);
+            self.add_a_(acc, op ^ 0xFF);
         } else {
-            // Nine's complement
-            let mut op = self.read(self.op_addr);
-            let op_lo = 9 - (op & 0xf);
-            let op_hi = 9 - (op >> 4);
-            op = (op_hi << 4) | op_lo;
-
-            self.add_dec_(acc, op);
+            self.sub_dec_(acc, op);
         }
     }
 
@@ -1133,43 +2484,95 @@ impl<T: IO> CPU6502<T> {
         self.set_arithmetic_status(self.a);
     }
 
+    /// Decimal mode addition
+    ///
+    /// Valid for all inputs, including invalid BCD values.
+    ///
+    /// http://www.6502.org/tutorials/decimal_mode.html#A
     #[inline]
     fn add_dec_(&mut self, a: u8, m: u8) {
         // BCD stores two digits (0-9) in a byte
-        // Sum hi and lo digits separately, then combine
-
-        // If the sum of the lo-bit digits (plus carry) exceeds 9, add 0x6 to skip the base-16 values.
-        // Carry the 1 to the hi-bit digit
+        // Sum lo and hi digits separately, then combine
         let c = (self.p & Status::C).bits();
-        let mut lo_carry = 0;
-        let mut lo_sum: u8 = (a & 0xF) + (m & 0xF) + c;
-        if lo_sum > 9 {
-            lo_sum += 0x6;
-            lo_carry = 0x1;
-        }
-
-        // If the sum of the hi-bit digits (plus lo-bit carry) exceeds 9, wrap around.
-        let mut hi_sum: u8 = (a >> 4) + (m >> 4) + lo_carry;
-        if hi_sum > 9 {
-            hi_sum -= 10;
-            self.p.set(Status::C, true);
+
+        // If the sum of the lo digits (plus carry) exceeds 9, add 0x6 to skip the base-16 values.
+        // Carry the 1 to the hi digit.
+        let mut lo_sum = (a as u16 & 0x0F) + (m as u16 & 0x0F) + c as u16;
+        if lo_sum >= 0x0A {
+            lo_sum = ((lo_sum + 0x06) & 0x0F) + 0x10;
+        }
+
+        // N and V are taken from the sum before the hi digit is adjusted,
+        // using signed arithmetic.
+        let signed_sum = (a & 0xF0) as i8 as i16 + (m & 0xF0) as i8 as i16 + lo_sum as i16;
+
+        // If the sum of the hi digits exceeds 9, wrap around and set the carry.
+        let mut sum = (a as u16 & 0xF0) + (m as u16 & 0xF0) + lo_sum;
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+
+        self.a = sum as u8;
+        self.p.set(Status::C, sum >= 0x100);
+        self.p.set(Status::V, !(-128..=127).contains(&signed_sum));
+
+        if self.is_cmos() {
+            // The 65C02 sets N and Z from the result, which takes an extra cycle.
+            self.set_arithmetic_status(self.a);
+            self.cycles_left += 1;
+            self.read(self.pc.wrapping_sub(1));
         } else {
-            self.p.set(Status::C, false);
+            // The NMOS 6502 sets Z as if the addition were binary.
+            self.p.set(Status::N, signed_sum & 0x80 != 0);
+            self.p.set(Status::Z, a.wrapping_add(m).wrapping_add(c) == 0);
         }
+    }
 
-        let sum: u8 = (hi_sum << 4) | (lo_sum & 0xF);
-        self.a = sum;
+    /// Decimal mode subtraction
+    ///
+    /// Valid for all inputs, including invalid BCD values.
+    ///
+    /// http://www.6502.org/tutorials/decimal_mode.html#A
+    #[inline]
+    fn sub_dec_(&mut self, a: u8, m: u8) {
+        let borrow = 1 - (self.p & Status::C).bits() as i16;
 
-        // Set Overflow flag
-        //
-        // Indicate overflow to negate the N flag when
-        // adding two values with the same sign (P + P or N + N).
-        self.p.set(
-            Status::V,
-            self.a & 0x80 != a & 0x80 && self.a & 0x80 != m & 0x80,
-        );
+        // Flags are set as if the subtraction were binary.
+        // The 65C02 overrides N and Z below.
+        self.add_a_(a, m ^ 0xFF);
 
-        self.set_arithmetic_status(self.a);
+        let lo_diff = (a as i16 & 0x0F) - (m as i16 & 0x0F) - borrow;
+
+        let diff = if self.is_cmos() {
+            let mut diff = a as i16 - m as i16 - borrow;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+            if lo_diff < 0 {
+                diff -= 0x06;
+            }
+            diff
+        } else {
+            // If the lo digit borrowed, subtract 0x6 to skip the base-16 values.
+            let mut lo_diff = lo_diff;
+            if lo_diff < 0 {
+                lo_diff = ((lo_diff - 0x06) & 0x0F) - 0x10;
+            }
+
+            let mut diff = (a as i16 & 0xF0) - (m as i16 & 0xF0) + lo_diff;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+            diff
+        };
+
+        self.a = diff as u8;
+
+        if self.is_cmos() {
+            self.set_arithmetic_status(self.a);
+            self.cycles_left += 1;
+            self.read(self.pc.wrapping_sub(1));
+        }
     }
 
     /// BCC - Branch if Carry Clear
@@ -1238,9 +2641,17 @@ impl<T: IO> CPU6502<T> {
 
     #[inline]
     fn branch_(&mut self) {
+        // While taking the branch, the next opcode is read and discarded.
+        self.read(self.pc);
+
+        self.branch_taken = true;
+
         // Add another cycle if page boundary was crossed.
-        if self.crossed_page_boundary(self.pc + 1, self.op_addr) {
+        // The unfixed address is read during that cycle.
+        if self.crossed_page_boundary(self.pc, self.op_addr) {
+            self.page_crossed = true;
             self.cycles_left += 1;
+            self.read((self.pc & 0xFF00) | (self.op_addr & 0x00FF));
         }
 
         self.pc = self.op_addr;
@@ -1269,7 +2680,9 @@ impl<T: IO> CPU6502<T> {
     /// DEC - Decrement
     ///
     fn dec(&mut self) {
-        let val = self.read(self.op_addr).wrapping_sub(1);
+        let m = self.read(self.op_addr);
+        self.rmw_dummy_(m);
+        let val = m.wrapping_sub(1);
 
         self.write(self.op_addr, val);
         self.set_arithmetic_status(val);
@@ -1293,6 +2706,7 @@ impl<T: IO> CPU6502<T> {
     /// M+1 -> M,N,Z
     fn inc(&mut self) {
         let m = self.read(self.op_addr);
+        self.rmw_dummy_(m);
         let result = m.wrapping_add(1);
         self.write(self.op_addr, result);
         self.set_arithmetic_status(result);
@@ -1318,6 +2732,11 @@ impl<T: IO> CPU6502<T> {
         let byte = self.read(self.op_addr);
         self.p.set(Status::Z, (self.a & byte) == 0);
 
+        // BIT # (65C02) only affects Z
+        if matches!(self.instruction, Some((_, (_, Mode::IMM, _, _)))) {
+            return;
+        }
+
         self.p.set(Status::V, byte >> 6 & 1 != 0);
         self.p.set(Status::N, byte >> 7 & 1 != 0);
 
@@ -1327,25 +2746,26 @@ impl<T: IO> CPU6502<T> {
     /// CMP - Compare Accumulator
     /// A-M -> Z,C,N
     fn cmp(&mut self) {
-        self.cmp_(self.a);
+        let m = self.read(self.op_addr);
+        self.cmp_(self.a, m);
     }
 
     /// CPX - Compare X
     /// X-M -> Z,C,N
     fn cpx(&mut self) {
-        self.cmp_(self.x);
+        let m = self.read(self.op_addr);
+        self.cmp_(self.x, m);
     }
 
     /// CPY - Compare Y
     /// Y-M -> Z,C,N
     fn cpy(&mut self) {
-        self.cmp_(self.y);
+        let m = self.read(self.op_addr);
+        self.cmp_(self.y, m);
     }
 
     #[inline]
-    fn cmp_(&mut self, value: u8) {
-        let m = self.read(self.op_addr);
-
+    fn cmp_(&mut self, value: u8, m: u8) {
         self.p.set(Status::Z, value == m);
         self.p.set(Status::C, value >= m);
         self.p.set(Status::N, value.wrapping_sub(m) & (1 << 7) != 0);
@@ -1362,19 +2782,28 @@ impl<T: IO> CPU6502<T> {
     ///
     /// http://www.obelisk.me.uk/6502/reference.html#JSR
     fn jsr(&mut self) {
-        let ret_addr = self.pc - 1;
+        let lo = self.pop_u8() as u16;
+        self.read_stack_();
+
+        // The return address is the last byte of the JSR instruction,
+        // which hasn't been fetched yet.
+        let ret_addr = self.pc;
 
         let ret_addr_hi = (ret_addr >> 8) as u8;
         let ret_addr_lo = ret_addr as u8;
         self.push_stack(ret_addr_hi);
         self.push_stack(ret_addr_lo);
 
+        let hi = self.pop_u8() as u16;
+        self.op_addr = (hi << 8) | lo;
+
         self.pc = self.op_addr;
     }
 
     /// RTI - Return from Interrupt
     ///
     fn rti(&mut self) {
+        self.read_stack_();
         let status = self.pop_stack();
         self.p =
             Status::from_bits(status).expect("Could not restore status") & !Status::B | Status::U;
@@ -1390,11 +2819,15 @@ impl<T: IO> CPU6502<T> {
     ///
     /// http://www.obelisk.me.uk/6502/reference.html#RTS
     fn rts(&mut self) {
+        self.read_stack_();
         let pc_lo = self.pop_stack() as u16;
         let pc_hi = self.pop_stack() as u16;
 
+        // The return address points at the last byte of the JSR instruction.
+        // It is read and discarded while the PC is incremented.
         let pc = (pc_hi << 8) | pc_lo;
-        self.pc = pc + 1;
+        self.read(pc);
+        self.pc = pc.wrapping_add(1);
     }
 
     /// LDA - Load Accumulator With Memory
@@ -1422,7 +2855,22 @@ impl<T: IO> CPU6502<T> {
     }
 
     /// NOP - No Operation
-    fn nop(&mut self) {}
+    ///
+    /// NOPs with an operand read it and discard the value.
+    /// Long 65C02 NOPs spend the rest of their cycles re-reading it.
+    fn nop(&mut self) {
+        if let Some((_, (_, mode, cycles, _))) = self.instruction {
+            if !matches!(mode, Mode::IMP | Mode::ACC) {
+                self.read(self.op_addr);
+            }
+
+            if self.is_cmos() {
+                while self.bus_index < cycles as usize {
+                    self.read(self.op_addr);
+                }
+            }
+        }
+    }
 
     /// ORA - OR Memory With Accumulator
     /// A|M -> A
@@ -1449,6 +2897,7 @@ impl<T: IO> CPU6502<T> {
     /// PLA - Pull Accumulator from Stack
     ///
     fn pla(&mut self) {
+        self.read_stack_();
         self.a = self.pop_stack();
         self.set_arithmetic_status(self.a);
     }
@@ -1456,6 +2905,7 @@ impl<T: IO> CPU6502<T> {
     /// PLP - Pull Processor Status
     ///
     fn plp(&mut self) {
+        self.read_stack_();
         self.p = Status::from_bits(self.pop_stack()).expect("Could not restore status register")
             & !(Status::B)
             | Status::U;
@@ -1547,11 +2997,378 @@ impl<T: IO> CPU6502<T> {
 
     /// BRK - Break
     ///
+    /// Software interrupt through the IRQ vector at $FFFE.
+    /// The byte after BRK is skipped, so the return address is BRK + 2.
+    /// B only exists in the copy of the status pushed to the stack,
+    /// which is how the handler tells BRK apart from an IRQ.
     fn brk(&mut self) {
-        self.p.set(Status::B, true);
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
 
-        self.interrupt_(0xFFFE);
+        let status = self.p | Status::B | Status::U;
+        self.interrupt_(0xFFFE, status);
+    }
+
+    //
+    // Undocumented operations
+    //
+    // Unstable opcodes (XAA, LXA, AHX, TAS, SHX, SHY) depend on analog effects
+    // on real silicon. They are emulated with the most commonly observed behavior
+    // so that results are deterministic.
+    //
+
+    /// SLO - Arithmetic Shift Left then OR
+    /// M << 1 -> M, A|M -> A
+    fn slo(&mut self) {
+        let byte = self.read(self.op_addr);
+        self.rmw_dummy_(byte);
+
+        let asl_value = self.asl_(byte);
+        self.write(self.op_addr, asl_value);
+
+        self.a |= asl_value;
+        self.set_arithmetic_status(self.a);
+    }
+
+    /// RLA - Rotate Left then AND
+    /// ROL M -> M, A&M -> A
+    fn rla(&mut self) {
+        let byte = self.read(self.op_addr);
+        self.rmw_dummy_(byte);
+
+        let rol_value = self.rol_(byte);
+        self.write(self.op_addr, rol_value);
+
+        self.a &= rol_value;
+        self.set_arithmetic_status(self.a);
+    }
+
+    /// SRE - Logical Shift Right then Exclusive OR
+    /// M >> 1 -> M, A^M -> A
+    fn sre(&mut self) {
+        let byte = self.read(self.op_addr);
+        self.rmw_dummy_(byte);
+
+        let lsr_value = self.lsr_(byte);
+        self.write(self.op_addr, lsr_value);
+
+        self.a ^= lsr_value;
+        self.set_arithmetic_status(self.a);
+    }
+
+    /// RRA - Rotate Right then Add with Carry
+    /// ROR M -> M, A+M+C -> A
+    fn rra(&mut self) {
+        let byte = self.read(self.op_addr);
+        self.rmw_dummy_(byte);
+
+        let ror_value = self.ror_(byte);
+        self.write(self.op_addr, ror_value);
+
+        self.adc_(ror_value);
+    }
+
+    /// DCP - Decrement then Compare
+    /// M-1 -> M, A-M -> Z,C,N
+    fn dcp(&mut self) {
+        let m = self.read(self.op_addr);
+        self.rmw_dummy_(m);
+        let val = m.wrapping_sub(1);
+        self.write(self.op_addr, val);
+
+        self.cmp_(self.a, val);
+    }
+
+    /// ISC - Increment then Subtract with Carry
+    /// M+1 -> M, A-M-!C -> A
+    fn isc(&mut self) {
+        let m = self.read(self.op_addr);
+        self.rmw_dummy_(m);
+        let val = m.wrapping_add(1);
+        self.write(self.op_addr, val);
+
+        self.sbc_(val);
+    }
+
+    /// SAX - Store A AND X
+    /// A&X -> M
+    fn sax(&mut self) {
+        self.write(self.op_addr, self.a & self.x);
+    }
+
+    /// LAX - Load Accumulator and X
+    /// M -> A,X
+    fn lax(&mut self) {
+        let m = self.read(self.op_addr);
+        self.a = m;
+        self.x = m;
+        self.set_arithmetic_status(m);
+    }
+
+    /// LAS - Load A, X and SP
+    /// M&SP -> A,X,SP
+    fn las(&mut self) {
+        let value = self.read(self.op_addr) & self.sp;
+        self.a = value;
+        self.x = value;
+        self.sp = value;
+        self.set_arithmetic_status(value);
+    }
+
+    /// ANC - AND Immediate then copy N to C
+    fn anc(&mut self) {
+        self.and();
+        self.p.set(Status::C, self.p.contains(Status::N));
+    }
+
+    /// ALR - AND Immediate then Logical Shift Right A
+    fn alr(&mut self) {
+        self.and();
+        self.lsr_a();
+    }
+
+    /// ARR - AND Immediate then Rotate Right A
+    ///
+    /// C is taken from bit 6 of the result and V from bit 6 XOR bit 5.
+    /// In decimal mode the NMOS 6502 applies a BCD fix-up to each nibble instead.
+    ///
+    /// http://www.oxyron.de/html/opcodes02.html
+    fn arr(&mut self) {
+        let and_value = self.a & self.read(self.op_addr);
+        let carry_in = if self.p.contains(Status::C) { 0x80 } else { 0 };
+        let mut value = (and_value >> 1) | carry_in;
+
+        if !self.p.contains(Status::D) {
+            self.set_arithmetic_status(value);
+            self.p.set(Status::C, value & 0x40 != 0);
+            self.p.set(Status::V, ((value >> 6) ^ (value >> 5)) & 1 != 0);
+        } else {
+            // N and Z reflect the binary result, V is set from the bit 6 change.
+            self.set_arithmetic_status(value);
+            self.p.set(Status::V, (and_value ^ value) & 0x40 != 0);
+
+            if (and_value & 0x0F) + (and_value & 0x01) > 0x05 {
+                value = (value & 0xF0) | (value.wrapping_add(0x06) & 0x0F);
+            }
+
+            let hi_fixup = (and_value as u16 & 0xF0) + (and_value as u16 & 0x10) > 0x50;
+            if hi_fixup {
+                value = value.wrapping_add(0x60);
+            }
+            self.p.set(Status::C, hi_fixup);
+        }
+
+        self.a = value;
+    }
+
+    /// SBX - Subtract Immediate from A AND X
+    /// (A&X)-M -> X
+    ///
+    /// Sets flags like CMP. Neither the carry nor decimal flag affect the result.
+    fn sbx(&mut self) {
+        let m = self.read(self.op_addr);
+        let a_and_x = self.a & self.x;
+
+        self.cmp_(a_and_x, m);
+        self.x = a_and_x.wrapping_sub(m);
+    }
+
+    /// XAA - Transfer X to A then AND Immediate. Unstable.
+    /// (A|MAGIC)&X&M -> A
+    ///
+    /// The "magic" constant varies between chips and with temperature.
+    /// We use 0xEE, the value most commonly observed.
+    fn xaa(&mut self) {
+        let m = self.read(self.op_addr);
+        self.a = (self.a | UNSTABLE_MAGIC) & self.x & m;
+        self.set_arithmetic_status(self.a);
+    }
+
+    /// LXA - Load A and X with Immediate. Unstable.
+    /// (A|MAGIC)&M -> A,X
+    ///
+    /// Uses the same magic constant as `XAA`.
+    fn lxa(&mut self) {
+        let m = self.read(self.op_addr);
+        self.a = (self.a | UNSTABLE_MAGIC) & m;
+        self.x = self.a;
+        self.set_arithmetic_status(self.a);
+    }
+
+    /// AHX - Store A AND X AND (H+1). Unstable.
+    fn ahx(&mut self) {
+        self.store_high_(self.a & self.x, self.y);
+    }
+
+    /// SHX - Store X AND (H+1). Unstable.
+    fn shx(&mut self) {
+        self.store_high_(self.x, self.y);
+    }
+
+    /// SHY - Store Y AND (H+1). Unstable.
+    fn shy(&mut self) {
+        self.store_high_(self.y, self.x);
+    }
+
+    /// TAS - Transfer A AND X to SP, then store SP AND (H+1). Unstable.
+    fn tas(&mut self) {
+        self.sp = self.a & self.x;
+        self.store_high_(self.sp, self.y);
+    }
+
+    /// Shared store behavior for `AHX`, `SHX`, `SHY` and `TAS`.
+    ///
+    /// The stored value is ANDed with the high byte of the base address plus one.
+    /// If indexing crossed a page boundary, the high byte of the effective address
+    /// is replaced by the stored value as well.
+    #[inline]
+    fn store_high_(&mut self, value: u8, index: u8) {
+        let base = self.op_addr.wrapping_sub(index as u16);
+        let hi = (base >> 8) as u8;
+        let value = value & hi.wrapping_add(1);
+
+        let addr = if self.crossed_page_boundary(base, self.op_addr) {
+            ((value as u16) << 8) | (self.op_addr & 0x00FF)
+        } else {
+            self.op_addr
+        };
+
+        self.write(addr, value);
+    }
+
+    /// JAM - Lock up the processor
+    ///
+    /// The real CPU stops fetching instructions and ignores interrupts until it is reset.
+    /// The PC is left pointing at the JAM opcode.
+    fn jam(&mut self) {
+        self.pc = self.pc.wrapping_sub(1);
+        self.stopped = true;
+    }
+
+    //
+    // 65C02 operations
+    //
+
+    /// BRA - Branch Always
+    fn bra(&mut self) {
+        self.branch_();
+    }
+
+    /// BBR - Branch on Bit Reset
+    ///
+    /// Tests a bit of a zero page location and branches if it is clear.
+    fn bbr_(&mut self, bit: u8) {
+        let m = self.read(self.op_addr);
+        self.read(self.op_addr);
+        self.rel();
+
+        if m & (1 << bit) == 0 {
+            self.cycles_left += 1;
+            self.branch_();
+        }
+    }
+
+    /// BBS - Branch on Bit Set
+    ///
+    /// Tests a bit of a zero page location and branches if it is set.
+    fn bbs_(&mut self, bit: u8) {
+        let m = self.read(self.op_addr);
+        self.read(self.op_addr);
+        self.rel();
+
+        if m & (1 << bit) != 0 {
+            self.cycles_left += 1;
+            self.branch_();
+        }
+    }
+
+    /// RMB - Reset Memory Bit
+    fn rmb_(&mut self, bit: u8) {
+        let m = self.read(self.op_addr);
+        self.rmw_dummy_(m);
+        self.write(self.op_addr, m & !(1 << bit));
+    }
+
+    /// SMB - Set Memory Bit
+    fn smb_(&mut self, bit: u8) {
+        let m = self.read(self.op_addr);
+        self.rmw_dummy_(m);
+        self.write(self.op_addr, m | (1 << bit));
+    }
+
+    /// DEC A - Decrement Accumulator
+    fn dec_a(&mut self) {
+        self.a = self.a.wrapping_sub(1);
+        self.set_arithmetic_status(self.a);
+    }
+
+    /// INC A - Increment Accumulator
+    fn inc_a(&mut self) {
+        self.a = self.a.wrapping_add(1);
+        self.set_arithmetic_status(self.a);
+    }
+
+    /// PHX - Push X to Stack
+    fn phx(&mut self) {
+        self.push_stack(self.x);
+    }
+
+    /// PHY - Push Y to Stack
+    fn phy(&mut self) {
+        self.push_stack(self.y);
+    }
+
+    /// PLX - Pull X from Stack
+    fn plx(&mut self) {
+        self.read_stack_();
+        self.x = self.pop_stack();
+        self.set_arithmetic_status(self.x);
+    }
+
+    /// PLY - Pull Y from Stack
+    fn ply(&mut self) {
+        self.read_stack_();
+        self.y = self.pop_stack();
+        self.set_arithmetic_status(self.y);
+    }
+
+    /// STZ - Store Zero
+    /// 0 -> M
+    fn stz(&mut self) {
+        self.write(self.op_addr, 0);
+    }
+
+    /// TRB - Test and Reset Bits
+    /// A&M -> Z, M&!A -> M
+    fn trb(&mut self) {
+        let m = self.read(self.op_addr);
+        self.rmw_dummy_(m);
+        self.p.set(Status::Z, self.a & m == 0);
+        self.write(self.op_addr, m & !self.a);
+    }
+
+    /// TSB - Test and Set Bits
+    /// A&M -> Z, M|A -> M
+    fn tsb(&mut self) {
+        let m = self.read(self.op_addr);
+        self.rmw_dummy_(m);
+        self.p.set(Status::Z, self.a & m == 0);
+        self.write(self.op_addr, m | self.a);
+    }
+
+    /// WAI - Wait for Interrupt
+    ///
+    /// The processor idles until an interrupt is signalled.
+    fn wai(&mut self) {
+        self.read(self.pc);
+        self.waiting = true;
+    }
+
+    /// STP - Stop the Processor
+    ///
+    /// The processor idles until it is reset.
+    fn stp(&mut self) {
+        self.read(self.pc);
+        self.stopped = true;
     }
 
     //
@@ -1560,29 +3377,72 @@ impl<T: IO> CPU6502<T> {
 
     // Interrupts
 
+    /// Check the IRQ and NMI lines at an instruction boundary.
+    ///
+    /// Returns the interrupt sequence to run, if any.
+    fn poll_interrupts(&mut self) -> Option<Interrupt> {
+        if self.stopped {
+            return None;
+        }
+
+        // Latch NMI on the rising edge
+        let nmi = self.nmi_line || self.mem.nmi();
+        if nmi && !self.nmi_prev {
+            self.nmi_pending = true;
+        }
+        self.nmi_prev = nmi;
+
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.waiting = false;
+            return Some(Interrupt::Nmi);
+        }
+
+        if self.irq_asserted() {
+            // WAI resumes on IRQ even when interrupts are disabled.
+            self.waiting = false;
+
+            if !self.irq_inhibit {
+                return Some(Interrupt::Irq);
+            }
+        }
+
+        None
+    }
+
     /// NMI - Non-Maskable Interrupt
-    // fn nmi(&mut self) {
-    //     self.interrupt_(0xFFFA);
-    //     self.cycle_count = 8;
-    // }
+    fn nmi(&mut self) {
+        // The next opcode is fetched twice and discarded.
+        self.read(self.pc);
+        self.read(self.pc);
+
+        let status = (self.p | Status::U) & !Status::B;
+        self.interrupt_(0xFFFA, status);
+        self.cycles_left = 6;
+        self.irq_inhibit = true;
+    }
 
     /// IRQ - Interrupt
     fn irq(&mut self) {
-        if !self.p.contains(Status::I) {
-            self.interrupt_(0xFFFE);
-            self.cycles_left = 7;
-        }
+        // The next opcode is fetched twice and discarded.
+        self.read(self.pc);
+        self.read(self.pc);
+
+        let status = (self.p | Status::U) & !Status::B;
+        self.interrupt_(0xFFFE, status);
+        self.cycles_left = 6;
+        self.irq_inhibit = true;
     }
 
-    fn interrupt_(&mut self, vector_addr: u16) {
-        // Push PC onto the stack
+    fn interrupt_(&mut self, vector_addr: u16, status: Status) {
+        // Push PC and status onto the stack
 
         let pc_hi = (self.pc >> 8) as u8;
         let pc_lo = self.pc as u8;
 
         self.push_stack(pc_hi);
         self.push_stack(pc_lo);
-        self.push_stack((self.p).bits());
+        self.push_stack(status.bits());
 
         // Set PC to address from vector
         let addr_lo = self.read(vector_addr) as u16;
@@ -1591,9 +3451,27 @@ impl<T: IO> CPU6502<T> {
 
         // Set I flag
         self.p.set(Status::I, true);
+
+        // The 65C02 also clears decimal mode
+        if self.is_cmos() {
+            self.p.set(Status::D, false);
+        }
+
         self.pc = addr;
     }
 
+    /// Read-modify-write instructions spend a cycle modifying the value.
+    /// The NMOS 6502 writes the unmodified value back during that cycle,
+    /// and the 65C02 reads it again instead.
+    #[inline]
+    fn rmw_dummy_(&mut self, value: u8) {
+        if self.is_cmos() {
+            self.read(self.op_addr);
+        } else {
+            self.write(self.op_addr, value);
+        }
+    }
+
     #[inline]
     fn crossed_page_boundary(&self, addr1: u16, addr2: u16) -> bool {
         addr1 & 0xFF00 != addr2 & 0xFF00
@@ -1624,8 +3502,14 @@ impl<T: IO> CPU6502<T> {
     }
 
     fn pop_u8(&mut self) -> u8 {
-        let addr = self.read(self.pc);
-        self.pc += 1;
+        let addr = match self.prefetched {
+            Some((start, operand)) if self.pc.wrapping_sub(start) < 2 => {
+                self.bus_index += 1;
+                operand[self.pc.wrapping_sub(start) as usize]
+            }
+            _ => self.read(self.pc),
+        };
+        self.pc = self.pc.wrapping_add(1);
 
         addr
     }
@@ -1637,6 +3521,12 @@ impl<T: IO> CPU6502<T> {
         self.sp = self.sp.wrapping_sub(1);
     }
 
+    /// Read the top of the stack without moving the stack pointer.
+    /// Stack instructions do this while the stack pointer is being updated.
+    fn read_stack_(&mut self) -> u8 {
+        self.read(STACK + (self.sp as u16))
+    }
+
     fn pop_stack(&mut self) -> u8 {
         self.sp = self.sp.wrapping_add(1);
 
@@ -1648,10 +3538,325 @@ impl<T: IO> CPU6502<T> {
 }
 
 impl<T: IO> IO for CPU6502<T> {
+    fn peek(&self, addr: u16) -> u8 {
+        self.mem.peek(addr)
+    }
     fn read(&mut self, addr: u16) -> u8 {
-        self.mem.read(addr)
+        let index = self.bus_index;
+        self.bus_index += 1;
+
+        if self.cached {
+            return self.mem.read(addr);
+        }
+
+        match self.replay {
+            Some(next) if index < next => return self.bus_log[index].value,
+            Some(next) if index > next => return 0,
+            _ => {}
+        }
+
+        let value = self.mem.read(addr);
+        self.bus_log.push(BusAccess {
+            addr,
+            value,
+            kind: AccessKind::Read,
+        });
+        value
     }
     fn write(&mut self, addr: u16, data: u8) {
-        self.mem.write(addr, data)
+        let index = self.bus_index;
+        self.bus_index += 1;
+
+        if let Some(next) = self.replay {
+            if index != next {
+                return;
+            }
+        }
+
+        self.mem.write(addr, data);
+        self.bus_log.push(BusAccess {
+            addr,
+            value: data,
+            kind: AccessKind::Write,
+        });
+
+        // Self-modifying code
+        if let Some(blocks) = &mut self.blocks {
+            if blocks.invalidate(addr) {
+                self.prefetched = None;
+            }
+        }
+    }
+}
+
+impl Checkpoint {
+    fn save_state(&self, w: &mut StateWriter, instruction_set: &[Instruction; 256]) {
+        w.u16(self.pc);
+        w.u8(self.a);
+        w.u8(self.x);
+        w.u8(self.y);
+        w.u8(self.sp);
+        w.u8(self.p.bits());
+        w.u64(self.instructions as u64);
+        save_instruction_(w, self.instruction, instruction_set);
+        w.u16(self.op_addr);
+        w.bool(self.waiting);
+        w.bool(self.stopped);
+        w.bool(self.irq_inhibit);
+        w.u8(match self.interrupt {
+            None => 0,
+            Some(Interrupt::Nmi) => 1,
+            Some(Interrupt::Irq) => 2,
+        });
+    }
+
+    fn load_state(r: &mut StateReader, instruction_set: &[Instruction; 256]) -> io::Result<Self> {
+        Ok(Self {
+            pc: r.u16()?,
+            a: r.u8()?,
+            x: r.u8()?,
+            y: r.u8()?,
+            sp: r.u8()?,
+            p: Status::from_bits_truncate(r.u8()?),
+            instructions: r.u64()? as usize,
+            instruction: load_instruction_(r, instruction_set)?,
+            op_addr: r.u16()?,
+            waiting: r.bool()?,
+            stopped: r.bool()?,
+            irq_inhibit: r.bool()?,
+            interrupt: match r.u8()? {
+                1 => Some(Interrupt::Nmi),
+                2 => Some(Interrupt::Irq),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Instructions are saved as their address and opcode byte.
+fn save_instruction_(
+    w: &mut StateWriter,
+    instruction: Option<(u16, Instruction)>,
+    instruction_set: &[Instruction; 256],
+) {
+    match instruction.and_then(|(addr, instruction)| {
+        let opcode = instruction_set.iter().position(|i| *i == instruction)?;
+        Some((addr, opcode as u8))
+    }) {
+        Some((addr, opcode)) => {
+            w.bool(true);
+            w.u16(addr);
+            w.u8(opcode);
+        }
+        None => w.bool(false),
+    }
+}
+
+fn load_instruction_(
+    r: &mut StateReader,
+    instruction_set: &[Instruction; 256],
+) -> io::Result<Option<(u16, Instruction)>> {
+    if !r.bool()? {
+        return Ok(None);
+    }
+
+    let addr = r.u16()?;
+    let opcode = r.u8()?;
+    Ok(Some((addr, instruction_set[opcode as usize])))
+}
+
+impl<T: IO + SaveState> SaveState for CPU6502<T> {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.save_core_state(w);
+        self.mem.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.load_core_state(r)?;
+        self.mem.load_state(r)
+    }
+}
+
+impl<T: IO> CPU6502<T> {
+    /// Save everything except the bus.
+    pub(crate) fn save_core_state(&self, w: &mut StateWriter) {
+        w.u8(match self.variant {
+            Variant::NMOS6502 => 0,
+            Variant::WDC65C02 => 1,
+        });
+        let instruction_set = self.instruction_set();
+
+        w.u16(self.pc);
+        w.u8(self.a);
+        w.u8(self.x);
+        w.u8(self.y);
+        w.u8(self.sp);
+        w.u8(self.p.bits());
+        w.u64(self.cycles);
+        w.u64(self.instructions as u64);
+        save_instruction_(w, self.instruction, instruction_set);
+        w.u16(self.op_addr);
+        w.u8(self.cycles_left);
+        w.bool(self.branch_taken);
+        w.bool(self.page_crossed);
+        w.bool(self.waiting);
+        w.bool(self.stopped);
+        w.u32(self.irq_lines);
+        w.bool(self.nmi_line);
+        w.bool(self.nmi_prev);
+        w.bool(self.nmi_pending);
+        w.bool(self.irq_inhibit);
+        w.bool(self.cycle_accurate);
+
+        // An instruction in flight in cycle-accurate mode
+        w.u32(self.bus_log.len() as u32);
+        for access in &self.bus_log {
+            w.u16(access.addr);
+            w.u8(access.value);
+            w.bool(access.kind == AccessKind::Write);
+        }
+        match &self.checkpoint {
+            Some(checkpoint) => {
+                w.bool(true);
+                checkpoint.save_state(w, instruction_set);
+            }
+            None => w.bool(false),
+        }
+    }
+
+    pub(crate) fn load_core_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.variant = match r.u8()? {
+            1 => Variant::WDC65C02,
+            _ => Variant::NMOS6502,
+        };
+        let instruction_set = self.instruction_set();
+
+        self.pc = r.u16()?;
+        self.a = r.u8()?;
+        self.x = r.u8()?;
+        self.y = r.u8()?;
+        self.sp = r.u8()?;
+        self.p = Status::from_bits_truncate(r.u8()?);
+        self.cycles = r.u64()?;
+        self.instructions = r.u64()? as usize;
+        self.instruction = load_instruction_(r, instruction_set)?;
+        self.op_addr = r.u16()?;
+        self.cycles_left = r.u8()?;
+        self.branch_taken = r.bool()?;
+        self.page_crossed = r.bool()?;
+        self.waiting = r.bool()?;
+        self.stopped = r.bool()?;
+        self.irq_lines = r.u32()?;
+        self.nmi_line = r.bool()?;
+        self.nmi_prev = r.bool()?;
+        self.nmi_pending = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.cycle_accurate = r.bool()?;
+
+        let accesses = r.u32()?;
+        self.bus_log.clear();
+        for _ in 0..accesses {
+            self.bus_log.push(BusAccess {
+                addr: r.u16()?,
+                value: r.u8()?,
+                kind: if r.bool()? {
+                    AccessKind::Write
+                } else {
+                    AccessKind::Read
+                },
+            });
+        }
+        self.checkpoint = if r.bool()? {
+            Some(Checkpoint::load_state(r, instruction_set)?)
+        } else {
+            None
+        };
+        self.replay = None;
+        self.flush_block_cache();
+        Ok(())
+    }
+}
+
+/// Registers and instruction state between instructions, for the history.
+/// A plain copy, so it can be taken after every instruction.
+#[derive(Clone, Copy)]
+pub(crate) struct Registers {
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u8,
+    p: Status,
+    cycles: u64,
+    instructions: usize,
+    instruction: Option<(u16, Instruction)>,
+    op_addr: u16,
+    cycles_left: u8,
+    branch_taken: bool,
+    page_crossed: bool,
+    waiting: bool,
+    stopped: bool,
+    irq_lines: u32,
+    nmi_line: bool,
+    nmi_prev: bool,
+    nmi_pending: bool,
+    irq_inhibit: bool,
+    checkpoint: Option<Checkpoint>,
+}
+
+impl<T: IO> CPU6502<T> {
+    pub(crate) fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            sp: self.sp,
+            p: self.p,
+            cycles: self.cycles,
+            instructions: self.instructions,
+            instruction: self.instruction,
+            op_addr: self.op_addr,
+            cycles_left: self.cycles_left,
+            branch_taken: self.branch_taken,
+            page_crossed: self.page_crossed,
+            waiting: self.waiting,
+            stopped: self.stopped,
+            irq_lines: self.irq_lines,
+            nmi_line: self.nmi_line,
+            nmi_prev: self.nmi_prev,
+            nmi_pending: self.nmi_pending,
+            irq_inhibit: self.irq_inhibit,
+            checkpoint: self.checkpoint,
+        }
+    }
+
+    /// Put back registers taken with `registers`. RAM may have changed
+    /// underneath, so the block cache is flushed.
+    pub(crate) fn set_registers(&mut self, registers: &Registers) {
+        self.pc = registers.pc;
+        self.a = registers.a;
+        self.x = registers.x;
+        self.y = registers.y;
+        self.sp = registers.sp;
+        self.p = registers.p;
+        self.cycles = registers.cycles;
+        self.instructions = registers.instructions;
+        self.instruction = registers.instruction;
+        self.op_addr = registers.op_addr;
+        self.cycles_left = registers.cycles_left;
+        self.branch_taken = registers.branch_taken;
+        self.page_crossed = registers.page_crossed;
+        self.waiting = registers.waiting;
+        self.stopped = registers.stopped;
+        self.irq_lines = registers.irq_lines;
+        self.nmi_line = registers.nmi_line;
+        self.nmi_prev = registers.nmi_prev;
+        self.nmi_pending = registers.nmi_pending;
+        self.irq_inhibit = registers.irq_inhibit;
+        self.checkpoint = registers.checkpoint;
+        self.bus_log.clear();
+        self.replay = None;
+        self.flush_block_cache();
     }
 }
@@ -0,0 +1,168 @@
+use super::{Instruction, Mode, Opcode, Variant, CPU6502};
+use crate::io::IO;
+
+/// Longest block decoded in one go
+const MAX_BLOCK_OPS: usize = 32;
+
+/// Sets `op_addr` for an instruction, returning whether indexing crossed a page.
+pub(super) type Addressing<T> = fn(&mut CPU6502<T>) -> bool;
+
+/// Carries out an instruction once its address is known.
+pub(super) type Handler<T> = fn(&mut CPU6502<T>);
+
+/// A decoded instruction with its operand bytes already fetched, and its
+/// addressing mode and handler looked up.
+pub(super) struct Op<T: IO> {
+    pub instruction: Instruction,
+    pub operand: [u8; 2],
+    pub addressing: Addressing<T>,
+    pub handler: Handler<T>,
+}
+
+impl<T: IO> Clone for Op<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: IO> Copy for Op<T> {}
+
+/// Whether execution can continue with whatever follows this instruction.
+fn falls_through(instruction: Instruction) -> bool {
+    let (opcode, mode, ..) = instruction;
+    !matches!(mode, Mode::REL | Mode::ZPR)
+        && !matches!(
+            opcode,
+            Opcode::JMP
+                | Opcode::JSR
+                | Opcode::RTS
+                | Opcode::RTI
+                | Opcode::BRK
+                | Opcode::JAM
+                | Opcode::STP
+                | Opcode::WAI
+        )
+}
+
+/// Decoded code, indexed by address.
+///
+/// Code is decoded a basic block at a time: a straight run of instructions
+/// up to the first one that can jump. Decoded instructions are dropped when
+/// the CPU writes to one of their bytes, so self-modifying code is picked up.
+/// Everything is dropped when the variant or the bus's memory map changes.
+pub(super) struct BlockCache<T: IO> {
+    ops: Vec<Option<Op<T>>>,
+    /// Number of decoded instructions starting in each page
+    pages: Vec<u16>,
+    variant: Variant,
+    map_generation: u64,
+}
+
+impl<T: IO> BlockCache<T> {
+    pub fn new(variant: Variant, map_generation: u64) -> Self {
+        Self {
+            ops: vec![None; 0x10000],
+            pages: vec![0; 0x100],
+            variant,
+            map_generation,
+        }
+    }
+
+    /// Drop everything.
+    pub fn flush(&mut self) {
+        for (page, count) in self.pages.iter_mut().enumerate() {
+            if *count > 0 {
+                let start = page << 8;
+                for op in &mut self.ops[start..start + 0x100] {
+                    *op = None;
+                }
+                *count = 0;
+            }
+        }
+    }
+
+    /// Drop the instructions containing `addr`. Returns whether there were any.
+    #[inline]
+    pub fn invalidate(&mut self, addr: u16) -> bool {
+        // An instruction containing `addr` starts at most two bytes before it.
+        if self.pages[(addr >> 8) as usize] == 0
+            && self.pages[(addr.wrapping_sub(2) >> 8) as usize] == 0
+        {
+            return false;
+        }
+
+        let mut hit = false;
+        for offset in 0..3 {
+            let start = addr.wrapping_sub(offset);
+            match self.ops[start as usize] {
+                Some(op) if offset < op.instruction.1.size() => {
+                    self.ops[start as usize] = None;
+                    self.pages[(start >> 8) as usize] -= 1;
+                    hit = true;
+                }
+                _ => {}
+            }
+        }
+        hit
+    }
+
+    /// Fetch the instruction at `pc`, decoding a new block if needed.
+    #[inline]
+    pub fn fetch(
+        &mut self,
+        pc: u16,
+        mem: &T,
+        variant: Variant,
+        instruction_set: &[Instruction; 256],
+    ) -> Op<T> {
+        let map_generation = mem.map_generation();
+        if variant != self.variant || map_generation != self.map_generation {
+            self.flush();
+            self.variant = variant;
+            self.map_generation = map_generation;
+        }
+
+        match self.ops[pc as usize] {
+            Some(op) => op,
+            None => self.decode(pc, mem, instruction_set),
+        }
+    }
+
+    /// Decode the block starting at `start` and return its first instruction.
+    ///
+    /// Decoding looks ahead of what will run, so memory is read with `peek`
+    /// to leave devices next to the code alone.
+    fn decode(&mut self, start: u16, mem: &T, instruction_set: &[Instruction; 256]) -> Op<T> {
+        let mut pc = start;
+
+        for _ in 0..MAX_BLOCK_OPS {
+            if self.ops[pc as usize].is_some() {
+                // Ran into code that's already decoded
+                break;
+            }
+
+            let instruction = instruction_set[mem.peek(pc) as usize];
+            let size = instruction.1.size();
+            let mut op = Op {
+                instruction,
+                operand: [0; 2],
+                addressing: CPU6502::addressing_(instruction),
+                handler: CPU6502::handler_(instruction.0),
+            };
+            for offset in 1..size {
+                op.operand[offset as usize - 1] = mem.peek(pc.wrapping_add(offset));
+            }
+
+            self.ops[pc as usize] = Some(op);
+            self.pages[(pc >> 8) as usize] += 1;
+
+            let next = pc.wrapping_add(size);
+            if !falls_through(instruction) || next < pc {
+                break;
+            }
+            pc = next;
+        }
+
+        self.ops[start as usize].unwrap()
+    }
+}
@@ -1,10 +1,13 @@
 use std::{
     borrow::BorrowMut,
     cell::RefCell,
+    fs,
+    io::{self, Write},
     ops::Deref,
+    path::Path,
     rc::Rc,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender, TryRecvError},
         Arc,
     },
     thread::{self, JoinHandle},
@@ -15,75 +18,150 @@ use parking_lot::Mutex;
 
 use crate::{
     bus::Bus,
-    cpu::{Mode, CPU6502, INSTRUCTIONS},
-    display::Display,
+    cpu::{AccessKind, HaltReason, Mode, Opcode, StepResult, CPU6502},
+    history::History,
     io::IO,
-    mem::Memory,
-    serial::Serial,
-    stdin::Stdin,
-    stdout::Stdout,
+    machine::Machine,
+    snapshot::Snapshot,
+    state::{SaveState, StateReader, StateWriter},
 };
 
+/// Commands sent to the run thread.
 pub enum CpuMessage {
+    /// Stop running and leave the CPU where it is
     Pause,
 }
 
-static HALT: AtomicBool = AtomicBool::new(true);
+/// What the CPU is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    /// Stopped by the user, or not started yet
+    Paused,
+    /// Free-running on the run thread
+    Running,
+    /// Executing a single instruction
+    Stepping,
+    /// Stopped on its own
+    Halted(HaltReason),
+}
+
+/// Destination for nestest-style trace lines.
+type Trace = Arc<Mutex<dyn Write + Send>>;
+
+/// Number of instructions that can be stepped back by default
+const HISTORY_LIMIT: usize = 100_000;
 
 pub struct Debugger {
-    pub cpu: Arc<Mutex<CPU6502<Bus>>>,
+    pub machine: Arc<Mutex<Machine>>,
     pub instruction_log: Vec<(u16, String)>,
     pub breakpoints: Vec<u16>,
+    /// Halt after an instruction writes to any of these addresses
+    pub watchpoints: Vec<u16>,
+    /// Halt once the CPU has run this many cycles
+    pub cycle_limit: Option<u64>,
+    /// Halt after `BRK` has vectored through $FFFE, treating it as the end of the program.
+    /// Disable this for firmware that uses `BRK` as a monitor or system call entry.
+    pub halt_on_brk: bool,
+    /// Halt on a branch or jump to itself with IRQs masked.
+    /// Disable this for firmware that idles in a loop waiting for an NMI.
+    pub halt_on_loop: bool,
     pub clock_speed: Option<u64>,
     pub non_interactive_mode: bool,
     pub max_speed: bool,
+    state: Arc<Mutex<RunState>>,
+    commands: Option<Sender<CpuMessage>>,
+    run_thread: Option<JoinHandle<()>>,
+    // Snapshots published by the run thread, and the latest one received
+    snapshots: Receiver<Arc<Snapshot>>,
+    snapshot_sender: SyncSender<Arc<Snapshot>>,
+    snapshot: Option<Arc<Snapshot>>,
+    trace: Option<Trace>,
+    history: Arc<Mutex<History>>,
 }
 
-impl Debugger {
-    pub fn new() -> Self {
-        let mem = Memory::new();
-        let stdout = Stdout::new();
-        let stdin = Stdin::new();
-        let display = Display::new();
-        let serial = Serial::new("/dev/tty.debug-console").expect("Could not open serial port");
-
-        let bus = Bus {
-            mem,
-            stdout,
-            stdin,
-            display,
-            serial,
-        };
+/// Everything the run loop checks after each instruction.
+#[derive(Clone)]
+struct HaltConditions {
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<u16>,
+    cycle_limit: Option<u64>,
+    halt_on_brk: bool,
+    halt_on_loop: bool,
+}
 
-        let cpu = Arc::new(Mutex::new(CPU6502::new(bus)));
+impl HaltConditions {
+    fn check(&self, cpu: &CPU6502<Bus>) -> Option<HaltReason> {
+        if let Some(reason) = cpu.halt_reason() {
+            if self.halt_on_loop || !matches!(reason, HaltReason::InfiniteLoop(_)) {
+                return Some(reason);
+            }
+        }
+
+        if let Some((addr, (Opcode::BRK, ..))) = cpu.instruction {
+            if self.halt_on_brk {
+                return Some(HaltReason::Break(addr));
+            }
+        }
+
+        if let Some(access) = cpu.bus_accesses().iter().find(|access| {
+            access.kind == AccessKind::Write && self.watchpoints.contains(&access.addr)
+        }) {
+            return Some(HaltReason::Watchpoint(access.addr));
+        }
+
+        if self.breakpoints.contains(&cpu.pc) {
+            return Some(HaltReason::Breakpoint(cpu.pc));
+        }
+
+        match self.cycle_limit {
+            Some(limit) if cpu.cycles >= limit => Some(HaltReason::CycleLimit(cpu.cycles)),
+            _ => None,
+        }
+    }
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Self {
+        let (snapshot_sender, snapshots) = mpsc::sync_channel(1);
+        let clock_speed = machine.cpu_clock();
 
-        let m = Debugger {
-            cpu,
+        let mut m = Debugger {
+            machine: Arc::new(Mutex::new(machine)),
             instruction_log: vec![],
             breakpoints: vec![],
-            clock_speed: Some(2_000_000),
+            watchpoints: vec![],
+            cycle_limit: None,
+            halt_on_brk: true,
+            halt_on_loop: true,
+            clock_speed: Some(clock_speed),
             non_interactive_mode: false,
             max_speed: false,
+            state: Arc::new(Mutex::new(RunState::Paused)),
+            commands: None,
+            run_thread: None,
+            snapshots,
+            snapshot_sender,
+            snapshot: None,
+            trace: None,
+            history: Arc::new(Mutex::new(History::new(HISTORY_LIMIT))),
         };
+        m.instruction_log = m.disassemble();
         m
     }
 
-    pub fn disassemble(&mut self) -> Vec<(u16, String)> {
+    pub fn disassemble(&self) -> Vec<(u16, String)> {
         let mut instructions = vec![];
+        let instruction_set = self.machine.lock().cpu.instruction_set();
 
         let mut addr = 0;
         while addr < 0xFFFF - 2 {
-            let opcode: u8 = self.read(addr);
+            let opcode: u8 = self.peek(addr);
 
-            let instruction = INSTRUCTIONS[opcode as usize];
-            let next_instr_addr = match instruction.1 {
-                Mode::ACC | Mode::IMP => addr + 1,
-                Mode::ABS | Mode::ABX | Mode::ABY => addr + 3,
-                _ => addr + 2,
-            };
+            let instruction = instruction_set[opcode as usize];
+            let next_instr_addr = addr + instruction.1.size();
 
-            let op8 = self.read(addr + 1);
-            let op16: u16 = ((self.read(addr + 2) as u16) << 8) | (self.read(addr + 1) as u16);
+            let op8 = self.peek(addr + 1);
+            let op16: u16 = ((self.peek(addr + 2) as u16) << 8) | (self.peek(addr + 1) as u16);
 
             let formatted_operand = match instruction.1 {
                 Mode::IMP => "".to_string(),
@@ -97,8 +175,11 @@ impl Debugger {
                 Mode::ZPY => format!("${:02X},Y", op8),
                 Mode::ZIX => format!("(${:02X},X)", op8),
                 Mode::ZIY => format!("(${:02X},Y)", op8),
-                Mode::IND => format!("(${:04X})", op8),
+                Mode::IND => format!("(${:04X})", op16),
                 Mode::REL => format!("${:02X}", op8),
+                Mode::ZPI => format!("(${:02X})", op8),
+                Mode::IAX => format!("(${:04X},X)", op16),
+                Mode::ZPR => format!("${:02X},${:02X}", op8, op16 >> 8),
             };
             instructions.push((addr, format!("{:#?} {}", instruction.0, &formatted_operand)));
             addr = next_instr_addr
@@ -107,56 +188,221 @@ impl Debugger {
     }
 
     pub fn load(&mut self, data: &[u8], offset: u16) {
-        self.cpu.lock().mem.mem.load(data, offset);
+        self.machine.lock().load(data, offset);
+
+        self.history.lock().clear();
         self.instruction_log = self.disassemble();
     }
 
-    pub fn step(&mut self) {
-        let mut cpu = self.cpu.lock();
+    pub fn step(&mut self) -> StepResult {
+        self.pause();
+        *self.state.lock() = RunState::Stepping;
+
+        let conditions = self.halt_conditions();
+        let mut machine = self.machine.lock();
+        let cpu = &mut machine.cpu;
+
+        let result = self.history.lock().record(cpu, |cpu| {
+            traced_(cpu, &self.trace, |cpu| cpu.step_instruction())
+        });
+        if let Some(trace) = &self.trace {
+            let _ = trace.lock().flush();
+        }
+
+        *self.state.lock() = match conditions.check(cpu) {
+            Some(reason) => RunState::Halted(reason),
+            None => RunState::Paused,
+        };
+        result
+    }
+
+    /// Save the complete machine state to a file.
+    pub fn save_state<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = StateWriter::new();
+        self.machine.lock().save_state(&mut w);
+        fs::write(path, w.into_bytes())
+    }
+
+    /// Restore the machine from a file written by `save_state`.
+    /// The state is validated before anything is changed.
+    pub fn load_state<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let data = fs::read(path)?;
+        self.pause();
+        let mut machine = self.machine.lock();
+
+        let mut r = StateReader::new(&data)?;
+        let mut w = StateWriter::new();
+        machine.save_state(&mut w);
+        if let Err(err) = machine.load_state(&mut r).and_then(|_| r.finish()) {
+            // Put back the machine as it was
+            let backup = w.into_bytes();
+            let _ = machine.load_state(&mut StateReader::new(&backup)?);
+            return Err(err);
+        }
+        drop(machine);
+
+        *self.state.lock() = RunState::Paused;
+        self.history.lock().clear();
+        self.instruction_log = self.disassemble();
+        Ok(())
+    }
+
+    /// Undo the last instruction. Returns false once the history runs out.
+    pub fn step_back(&mut self) -> bool {
+        self.pause();
+        *self.state.lock() = RunState::Paused;
+        self.history.lock().step_back(&mut self.machine.lock().cpu)
+    }
+
+    /// Step backwards until the PC reaches a breakpoint or the history runs out.
+    pub fn run_back(&mut self) -> Option<HaltReason> {
+        self.pause();
+        let mut machine = self.machine.lock();
+        let cpu = &mut machine.cpu;
+        let mut history = self.history.lock();
+
+        let mut reason = None;
+        while history.step_back(cpu) {
+            if self.breakpoints.contains(&cpu.pc) {
+                reason = Some(HaltReason::Breakpoint(cpu.pc));
+                break;
+            }
+        }
+
+        *self.state.lock() = match reason {
+            Some(reason) => RunState::Halted(reason),
+            None => RunState::Paused,
+        };
+        reason
+    }
+
+    /// Step backwards to the last instruction boundary at or before `cycle`.
+    /// Returns false if the history doesn't reach back that far.
+    pub fn rewind_to(&mut self, cycle: u64) -> bool {
+        self.pause();
+        *self.state.lock() = RunState::Paused;
+        let mut machine = self.machine.lock();
+        let cpu = &mut machine.cpu;
+        let mut history = self.history.lock();
+
+        while cpu.cycles > cycle {
+            if !history.step_back(cpu) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Number of instructions that can currently be stepped back
+    pub fn history_len(&self) -> usize {
+        self.history.lock().len()
+    }
+
+    /// Keep at most `limit` instructions of history. Zero turns recording off.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.lock().set_limit(limit);
+    }
 
-        cpu.clock();
-        while !cpu.halted() && cpu.cycles_left > 0 {
-            cpu.clock();
+    /// Write a line in the nestest.log layout for every instruction executed.
+    pub fn trace_to<W: Write + Send + 'static>(&mut self, writer: W) {
+        self.trace = Some(Arc::new(Mutex::new(writer)));
+    }
+
+    pub fn state(&self) -> RunState {
+        *self.state.lock()
+    }
+
+    /// Latest state of the machine for display.
+    ///
+    /// While running, this is the last snapshot published by the run thread,
+    /// so it never waits for the CPU. Otherwise it is taken on the spot.
+    pub fn snapshot(&mut self) -> Arc<Snapshot> {
+        while let Ok(snapshot) = self.snapshots.try_recv() {
+            self.snapshot = Some(snapshot);
+        }
+
+        match &self.snapshot {
+            Some(snapshot) if self.state() == RunState::Running => snapshot.clone(),
+            _ => {
+                let machine = self.machine.lock();
+                let snapshot = Arc::new(Snapshot::capture(&machine.cpu, self.history.lock().len()));
+                self.snapshot = Some(snapshot.clone());
+                snapshot
+            }
         }
     }
 
+    /// Whether the CPU is stopped, for whatever reason.
     pub fn is_halted(&self) -> bool {
-        HALT.load(Ordering::Relaxed)
+        self.state() != RunState::Running
+    }
+
+    /// Why execution last stopped, if it stopped on its own
+    /// rather than being paused.
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        match self.state() {
+            RunState::Halted(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    fn halt_conditions(&self) -> HaltConditions {
+        HaltConditions {
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: self.watchpoints.clone(),
+            cycle_limit: self.cycle_limit,
+            halt_on_brk: self.halt_on_brk,
+            halt_on_loop: self.halt_on_loop,
+        }
     }
 
     pub fn reset(&mut self) {
-        let mut cpu = self.cpu.lock();
-
-        cpu.reset();
-        self.breakpoints = vec![
-            // dec mode success
-            0x3469,
-            // non-dec success/dec mode start!
-            // 0x336D,
-            // 0x346f,
-            // 0x3484,
-            // 0x3479,
-            // 0x3470,
-            // decimal mode adc/sbc
-            // 0x346F
-        ]
-        // cpu.pc = 0x3387;
-        // cpu.pc = 0x331C;
-        // cpu.pc = 0x36AD;
+        self.pause();
+        self.machine.lock().reset();
+        *self.state.lock() = RunState::Paused;
+        self.history.lock().clear();
     }
 
     pub fn show(&mut self) {
         // self.bus.borrow_mut().display.show();
     }
 
+    /// Stop the run thread, if there is one, and wait for it to finish.
     pub fn pause(&mut self) {
-        HALT.store(true, Ordering::Relaxed)
+        if let Some(commands) = self.commands.take() {
+            let _ = commands.send(CpuMessage::Pause);
+        }
+        if let Some(run_thread) = self.run_thread.take() {
+            let _ = run_thread.join();
+        }
     }
 
-    pub fn run(&mut self) -> Option<JoinHandle<()>> {
-        HALT.store(false, Ordering::Relaxed);
+    /// Wait for the run thread to halt on its own, and return why it did.
+    pub fn wait(&mut self) -> Option<HaltReason> {
+        if let Some(run_thread) = self.run_thread.take() {
+            let _ = run_thread.join();
+        }
+        self.commands = None;
+        self.halt_reason()
+    }
 
-        let breakpoints = self.breakpoints.clone();
+    /// Start running on a separate thread. Does nothing if already running.
+    pub fn run(&mut self) {
+        if self.state() == RunState::Running {
+            return;
+        }
+        // Clean up after a run that halted on its own
+        self.pause();
+
+        let (commands, receiver) = mpsc::channel();
+        self.commands = Some(commands);
+        *self.state.lock() = RunState::Running;
+
+        let conditions = self.halt_conditions();
+        let state = self.state.clone();
+        let trace = self.trace.clone();
+        let history = self.history.clone();
+        let snapshots = self.snapshot_sender.clone();
         let clock_speed: u64 = self.clock_speed.unwrap_or(1_000_000);
 
         let target_fps = 60;
@@ -164,55 +410,107 @@ impl Debugger {
         let ns_per_interval: u64 = 1_000_000_000 / target_fps;
         let max_speed = self.max_speed;
 
-        let cpu = self.cpu.clone();
+        let machine = self.machine.clone();
         let cpu_thread = thread::spawn(move || {
             let mut cycles_since_last_interval = 0;
             let mut time_to_next_interval = Instant::now() + Duration::from_nanos(ns_per_interval);
 
             // Run loop
             'running: loop {
-                let mut cpu = cpu.lock();
-                if HALT.load(Ordering::Relaxed) {
-                    break 'running;
+                let mut machine = machine.lock();
+                let cpu = &mut machine.cpu;
+                let mut history = history.lock();
+
+                // Commands are handled between intervals
+                match receiver.try_recv() {
+                    Ok(CpuMessage::Pause) | Err(TryRecvError::Disconnected) => {
+                        *state.lock() = RunState::Paused;
+                        break 'running;
+                    }
+                    Err(TryRecvError::Empty) => {}
                 }
 
-                // Execute current instruction
-                'execute: loop {
-                    cpu.clock();
-                    cycles_since_last_interval += 1;
-                    if cpu.cycles_left == 0 {
-                        break 'execute;
+                // Run a whole interval's worth of instructions under one lock
+                while cycles_since_last_interval <= cycles_per_interval {
+                    // Execute current instruction
+                    let cycles = cpu.cycles;
+                    history.record(cpu, |cpu| {
+                        traced_(cpu, &trace, |cpu| cpu.dispatch_instruction())
+                    });
+                    cycles_since_last_interval += cpu.cycles - cycles;
+
+                    // Check breakpoints and other halt conditions
+                    if let Some(reason) = conditions.check(cpu) {
+                        *state.lock() = RunState::Halted(reason);
+                        break 'running;
                     }
                 }
 
-                // Check breakpoints
-                if breakpoints.contains(&cpu.pc) {
-                    HALT.store(true, Ordering::Relaxed);
-                }
+                // Publish the state for the UI. If the last one hasn't been picked up, skip this one.
+                let _ = snapshots.try_send(Arc::new(Snapshot::capture(cpu, history.len())));
+                drop(history);
+                drop(machine);
 
                 // Instructions are executed as fast as the host is capable of running them.
                 // To simulate the speed of the original hardware, we wait out the remaining length of time in the frame (interval)
-                // before executing the next instruction. The interval length was calculated based on the desired clockspeed.
-                if !max_speed && cycles_since_last_interval > cycles_per_interval {
+                // before executing the next batch of instructions. The interval length was calculated based on the desired clockspeed.
+                if !max_speed {
                     let time_left_in_interval = time_to_next_interval - Instant::now();
                     if time_left_in_interval.as_nanos() > 0 {
                         thread::sleep(time_left_in_interval);
                     }
-
-                    cycles_since_last_interval = 0;
-                    time_to_next_interval = Instant::now() + Duration::from_nanos(ns_per_interval);
                 }
+
+                cycles_since_last_interval = 0;
+                time_to_next_interval = Instant::now() + Duration::from_nanos(ns_per_interval);
+            }
+
+            if let Some(trace) = &trace {
+                let _ = trace.lock().flush();
             }
         });
-        Some(cpu_thread)
+        self.run_thread = Some(cpu_thread);
+    }
+}
+
+impl Drop for Debugger {
+    fn drop(&mut self) {
+        self.pause();
     }
 }
 
+/// Run `f` and trace the instruction it executes, if any.
+/// Interrupt sequences and idle cycles aren't traced.
+fn traced_<R>(
+    cpu: &mut CPU6502<Bus>,
+    trace: &Option<Trace>,
+    f: impl FnOnce(&mut CPU6502<Bus>) -> R,
+) -> R {
+    let trace = match trace {
+        Some(trace) => trace,
+        None => return f(cpu),
+    };
+
+    let line = cpu.trace_line();
+    let instructions = cpu.instructions;
+    let result = f(cpu);
+
+    if cpu.instructions != instructions {
+        let _ = writeln!(trace.lock(), "{}", line);
+    }
+    result
+}
+
 impl IO for Debugger {
     fn read(&mut self, addr: u16) -> u8 {
-        self.cpu.lock().mem.read(addr)
+        self.machine.lock().cpu.mem.read(addr)
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.machine.lock().cpu.mem.peek(addr)
     }
     fn write(&mut self, addr: u16, data: u8) {
-        self.cpu.lock().mem.write(addr, data)
+        let mut machine = self.machine.lock();
+        machine.cpu.mem.write(addr, data);
+        machine.cpu.flush_block_cache();
     }
 }
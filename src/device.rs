@@ -0,0 +1,63 @@
+use std::ops::RangeInclusive;
+
+use crate::{io::IO, state::SaveState};
+
+/// Something that can be attached to the bus.
+///
+/// Addresses passed to `read` and `write` are relative to the start of the
+/// range the device is mapped at, so the same device works at any address.
+pub trait Device: IO + SaveState + Send {
+    /// Short name, used to check a save state matches the machine.
+    fn name(&self) -> &str;
+
+    /// Called with the CPU clock in Hz before the machine starts, for devices
+    /// that count time in cycles.
+    fn set_cpu_clock(&mut self, _hz: u64) {}
+}
+
+/// Identifies a device attached to a `Bus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId(pub(crate) usize);
+
+/// Where a device appears in the address space.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub range: RangeInclusive<u16>,
+    /// Registers repeat every this many bytes across the range.
+    pub mirror: Option<u16>,
+}
+
+impl Mapping {
+    pub fn new(range: RangeInclusive<u16>) -> Self {
+        Self {
+            range,
+            mirror: None,
+        }
+    }
+
+    /// Repeat the device's first `size` bytes across the whole range,
+    /// as hardware that only decodes the low address lines does.
+    pub fn mirrored(mut self, size: u16) -> Self {
+        self.mirror = Some(size);
+        self
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        self.range.contains(&addr)
+    }
+
+    /// Address as seen by the device.
+    pub fn relative(&self, addr: u16) -> u16 {
+        let offset = addr - self.range.start();
+        match self.mirror {
+            Some(size) if size > 0 => offset % size,
+            _ => offset,
+        }
+    }
+}
+
+impl From<RangeInclusive<u16>> for Mapping {
+    fn from(range: RangeInclusive<u16>) -> Self {
+        Self::new(range)
+    }
+}
@@ -1,6 +1,10 @@
 use sdl2::rect::Rect;
 
-use crate::io::IO;
+use crate::{
+    device::Device,
+    io::IO,
+    state::{SaveState, StateReader, StateWriter},
+};
 use std::{iter::FromIterator, time::Duration, sync::{Arc, Mutex}};
 
 /// Display from Easy6502
@@ -8,7 +12,8 @@ use std::{iter::FromIterator, time::Duration, sync::{Arc, Mutex}};
 ///
 /// https://skilldrick.github.io/easy6502/
 ///
-/// Responds to addresses $0200 - $05ff.
+/// Map it at $0200 - $05ff. Pixels read back as they were written.
+/// Only the low 10 address lines are decoded, so a larger mapping repeats them.
 pub struct Display {
     buffer: Arc<Mutex<[u8; 32 * 32]>>,
 }
@@ -90,10 +95,29 @@ impl Display {
 }
 
 impl IO for Display {
-    fn read(&mut self, _addr: u16) -> u8 {
-       0
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.buffer.lock().unwrap()[addr as usize & 0x3FF]
     }
     fn write(&mut self, addr: u16, data: u8) {
-        self.buffer.lock().unwrap()[addr as usize] = data
+        self.buffer.lock().unwrap()[addr as usize & 0x3FF] = data
+    }
+}
+
+impl Device for Display {
+    fn name(&self) -> &str {
+        "display"
+    }
+}
+
+impl SaveState for Display {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.buffer.lock().unwrap()[..]);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.bytes_into(&mut self.buffer.lock().unwrap()[..])
     }
 }
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+
+use crate::{
+    bus::Bus,
+    cpu::{Registers, CPU6502},
+};
+
+/// One executed instruction and what it takes to undo it.
+struct Entry {
+    /// CPU state before the instruction ran
+    registers: Registers,
+    /// Number of RAM writes the instruction made, kept at the back of `History::writes`
+    writes: usize,
+}
+
+/// Bounded record of executed instructions, for stepping backwards.
+///
+/// Only the CPU and RAM are rewound. Writes to devices, and bytes already
+/// sent to or received from them such as on the serial port, are not undone
+/// by `step_back`.
+pub struct History {
+    entries: VecDeque<Entry>,
+    /// RAM writes of every entry in order, with the values they replaced
+    writes: VecDeque<(u16, u8)>,
+    /// Handed to the bus to log each instruction's writes, and reused
+    journal: Vec<(u16, u8)>,
+    limit: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            writes: VecDeque::new(),
+            journal: vec![],
+            limit,
+        }
+    }
+
+    /// Maximum number of instructions kept. Zero turns recording off.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.entries.len() > limit {
+            self.pop_oldest_();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.writes.clear();
+    }
+
+    fn pop_oldest_(&mut self) {
+        if let Some(entry) = self.entries.pop_front() {
+            self.writes.drain(..entry.writes);
+        }
+    }
+
+    /// Run `f` and remember how to undo whatever it did to the machine.
+    pub fn record<R>(
+        &mut self,
+        cpu: &mut CPU6502<Bus>,
+        f: impl FnOnce(&mut CPU6502<Bus>) -> R,
+    ) -> R {
+        if self.limit == 0 {
+            return f(cpu);
+        }
+
+        let registers = cpu.registers();
+        let cycles = cpu.cycles;
+
+        self.journal.clear();
+        cpu.mem.journal = Some(std::mem::take(&mut self.journal));
+        let result = f(cpu);
+        self.journal = cpu.mem.journal.take().unwrap_or_default();
+
+        if cpu.cycles != cycles {
+            if self.entries.len() == self.limit {
+                self.pop_oldest_();
+            }
+            self.writes.extend(self.journal.iter().copied());
+            self.entries.push_back(Entry {
+                registers,
+                writes: self.journal.len(),
+            });
+        }
+        result
+    }
+
+    /// Undo the most recent instruction. Returns false if there is nothing left to undo.
+    pub fn step_back(&mut self, cpu: &mut CPU6502<Bus>) -> bool {
+        let entry = match self.entries.pop_back() {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        for _ in 0..entry.writes {
+            if let Some((addr, value)) = self.writes.pop_back() {
+                cpu.mem.mem.0[addr as usize] = value;
+            }
+        }
+
+        cpu.set_registers(&entry.registers);
+        true
+    }
+}
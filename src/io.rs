@@ -1,8 +1,33 @@
 pub trait IO {
     fn read(&mut self, addr: u16) -> u8;
 
+    /// What `read` would return, without any of its side effects, such as
+    /// acknowledging an interrupt or taking a byte from a receive buffer.
+    /// Debugger views use this so looking at a register doesn't change it.
+    fn peek(&self, addr: u16) -> u8;
+
     fn write(&mut self, addr: u16, data: u8);
 
+    /// Whether the device is asserting the IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Whether the device is asserting the NMI line.
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /// Let `cycles` CPU cycles pass. Called as the CPU runs, so devices can
+    /// keep time and change state or raise interrupts on the right cycle.
+    fn tick(&mut self, _cycles: u32) {}
+
+    /// Changes whenever the memory map changes, e.g. on a bank switch,
+    /// so any code the CPU has cached gets decoded again.
+    fn map_generation(&self) -> u64 {
+        0
+    }
+
     fn write_str(&mut self, addr: u16, str: &str) {
         for (i, c) in str.chars().enumerate() {
             self.write(addr + i as u16, c as u8);
@@ -32,5 +57,9 @@ impl IO for NullIO {
         0
     }
 
+    fn peek(&self, _addr: u16) -> u8 {
+        0
+    }
+
     fn write(&mut self, _addr: u16, _data: u8) {}
 }
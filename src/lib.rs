@@ -8,6 +8,12 @@ pub mod stdout;
 pub mod stdin;
 pub mod serial;
 pub mod bus;
+pub mod state;
+pub mod history;
+pub mod snapshot;
+pub mod machine;
+pub mod device;
+pub mod acia6850;
 
 #[macro_use]
 extern crate bitflags;
@@ -0,0 +1,194 @@
+use std::io;
+
+use crate::{
+    bus::{Bus, SERIAL_END, SERIAL_START},
+    cpu::{HaltReason, StepResult, Variant, CPU6502},
+    device::{Device, Mapping},
+    mem::Memory,
+    serial::{backend::Backend, Serial},
+    state::{SaveState, StateReader, StateWriter},
+};
+
+/// CPU clock in Hz unless `MachineBuilder::cpu_clock` says otherwise
+pub const DEFAULT_CPU_CLOCK: u64 = 2_000_000;
+
+/// A complete computer: the CPU and everything on its bus.
+///
+/// This is the emulator core without any user interface, so it can be
+/// driven directly by other tools. Use `Machine::builder` to put one together.
+pub struct Machine {
+    pub cpu: CPU6502<Bus>,
+    cpu_clock: u64,
+}
+
+impl Machine {
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::default()
+    }
+
+    /// CPU clock in Hz, as given to the builder.
+    pub fn cpu_clock(&self) -> u64 {
+        self.cpu_clock
+    }
+
+    /// Copy `data` into RAM starting at `offset`.
+    pub fn load(&mut self, data: &[u8], offset: u16) {
+        self.cpu.mem.mem.load(data, offset);
+        self.cpu.flush_block_cache();
+    }
+
+    /// Reset the CPU. Memory is left as it is.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    /// Execute a single instruction.
+    pub fn step(&mut self) -> StepResult {
+        self.cpu.step_instruction()
+    }
+
+    /// Run until `halt` returns a reason to stop, or the CPU stops on its own.
+    pub fn run<F>(&mut self, halt: F) -> HaltReason
+    where
+        F: FnMut(&CPU6502<Bus>) -> Option<HaltReason>,
+    {
+        self.cpu.run_until(halt)
+    }
+
+    /// Run for at least `cycles` more cycles.
+    pub fn run_for_cycles(&mut self, cycles: u64) -> HaltReason {
+        self.cpu.run_for_cycles(cycles)
+    }
+}
+
+impl SaveState for Machine {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.cpu.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.cpu.load_state(r)
+    }
+}
+
+/// Chooses the CPU, the devices and where they sit in the memory map.
+///
+/// By default this is an NMOS 6502 with RAM everywhere, and a serial port
+/// with nothing attached at $5000-$5FFF. Other devices are mapped over RAM
+/// with `device`.
+pub struct MachineBuilder {
+    variant: Variant,
+    cycle_accurate: bool,
+    block_cache: bool,
+    cpu_clock: u64,
+    serial_backend: Option<Box<dyn Backend>>,
+    serial_mapping: Option<Mapping>,
+    devices: Vec<(Box<dyn Device>, Vec<Mapping>)>,
+    images: Vec<(Vec<u8>, u16)>,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        Self {
+            variant: Variant::NMOS6502,
+            cycle_accurate: false,
+            block_cache: false,
+            cpu_clock: DEFAULT_CPU_CLOCK,
+            serial_backend: None,
+            serial_mapping: Some(Mapping::new(SERIAL_START..=SERIAL_END)),
+            devices: vec![],
+            images: vec![],
+        }
+    }
+}
+
+impl MachineBuilder {
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    /// Step through every bus access rather than whole instructions.
+    pub fn cycle_accurate(mut self, cycle_accurate: bool) -> Self {
+        self.cycle_accurate = cycle_accurate;
+        self
+    }
+
+    /// Use the block-caching interpreter when running.
+    pub fn block_cache(mut self, block_cache: bool) -> Self {
+        self.block_cache = block_cache;
+        self
+    }
+
+    /// CPU clock in Hz. Devices that count time in cycles, such as the
+    /// serial ports, are told it when the machine is built.
+    /// Panics if `hz` is 0.
+    pub fn cpu_clock(mut self, hz: u64) -> Self {
+        assert!(hz > 0, "the CPU clock can't be 0 Hz");
+        self.cpu_clock = hz;
+        self
+    }
+
+    /// Connect the serial port to the host through `backend`.
+    pub fn serial_backend(mut self, backend: Box<dyn Backend>) -> Self {
+        self.serial_backend = Some(backend);
+        self
+    }
+
+    /// Decode these addresses to the serial port, or leave it out with `None`.
+    pub fn serial_at(mut self, mapping: Option<Mapping>) -> Self {
+        self.serial_mapping = mapping;
+        self
+    }
+
+    /// Attach a device at one or more places in the memory map.
+    /// Devices take precedence over each other in the order they are added.
+    pub fn device<D: Device + 'static>(mut self, device: D, mappings: Vec<Mapping>) -> Self {
+        self.devices.push((Box::new(device), mappings));
+        self
+    }
+
+    /// Load an image into RAM at `offset` before reset.
+    pub fn image(mut self, data: &[u8], offset: u16) -> Self {
+        self.images.push((data.to_vec(), offset));
+        self
+    }
+
+    /// Assemble and reset the machine.
+    pub fn build(self) -> Machine {
+        let mut mem = Memory::new();
+        for (data, offset) in &self.images {
+            mem.load(data, *offset);
+        }
+
+        let mut bus = Bus::new(mem);
+        for (mut device, mappings) in self.devices {
+            device.set_cpu_clock(self.cpu_clock);
+            let id = bus.attach(device);
+            for mapping in mappings {
+                bus.map(id, mapping);
+            }
+        }
+
+        if let Some(mapping) = self.serial_mapping {
+            let mut serial = match self.serial_backend {
+                Some(backend) => Serial::new(backend),
+                None => Serial::disconnected(),
+            };
+            serial.set_cpu_clock(self.cpu_clock);
+            let id = bus.attach(Box::new(serial));
+            bus.map(id, mapping);
+        }
+
+        let mut cpu = CPU6502::new(bus);
+        cpu.variant = self.variant;
+        cpu.cycle_accurate = self.cycle_accurate;
+        cpu.block_cache = self.block_cache;
+        cpu.reset();
+
+        Machine {
+            cpu,
+            cpu_clock: self.cpu_clock,
+        }
+    }
+}
@@ -1,4 +1,9 @@
-use crate::io::IO;
+use std::io;
+
+use crate::{
+    io::IO,
+    state::{SaveState, StateReader, StateWriter},
+};
 
 pub struct Memory(pub [u8; 0xFFFF + 1]);
 
@@ -18,7 +23,20 @@ impl IO for Memory {
     fn read(&mut self, addr: u16) -> u8 {
         self.0[addr as usize]
     }
+    fn peek(&self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
     fn write(&mut self, addr: u16, data: u8) {
         self.0[addr as usize] = data;
     }
 }
+
+impl SaveState for Memory {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.0);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into(&mut self.0)
+    }
+}
@@ -1,70 +1,438 @@
-use crate::io::IO;
-use std::{io::Write, ops::Deref, time::Instant};
-use serialport::{self, SerialPort, TTYPort};
+use crate::{
+    device::Device,
+    io::IO,
+    machine::DEFAULT_CPU_CLOCK,
+    state::{SaveState, StateReader, StateWriter},
+};
+use std::io::{Error, ErrorKind};
+
+use self::backend::{Backend, NullBackend};
+
+pub mod backend;
+pub mod script;
 
 const ACIA_DATA: u16 = 0;
 const ACIA_STATUS: u16 = 1;
 const ACIA_COMMAND: u16 = 2;
 const ACIA_CONTROL: u16 = 3;
 
+/// Baud rates selected by the low nibble of the control register.
+/// Rate 0 is the 16x external receiver clock, taken to be the usual 1.8432 MHz crystal.
+const BAUD_RATES: [f64; 16] = [
+    115_200.0, 50.0, 75.0, 109.92, 134.58, 150.0, 300.0, 600.0, 1200.0, 1800.0, 2400.0,
+    3600.0, 4800.0, 7200.0, 9600.0, 19_200.0,
+];
+
 bitflags! {
     pub struct Status: u8 {
-        const TX_EMPTY = 1 << 4;
+        const PARITY_ERROR = 1 << 0;
+        const FRAMING_ERROR = 1 << 1;
+        const OVERRUN = 1 << 2;
         const RX_FULL = 1 << 3;
+        const TX_EMPTY = 1 << 4;
+        /// Data carrier detect, active low
+        const DCD = 1 << 5;
+        /// Data set ready, active low
+        const DSR = 1 << 6;
+        const IRQ = 1 << 7;
+    }
+}
+
+bitflags! {
+    pub struct Command: u8 {
+        /// Enable the receiver and interrupts, and assert DTR
+        const DTR = 1 << 0;
+        const RX_IRQ_DISABLE = 1 << 1;
+        /// Transmitter interrupt and RTS control
+        const TX_CONTROL = 0b11 << 2;
+        const ECHO = 1 << 4;
+        const PARITY_ENABLE = 1 << 5;
+        const PARITY_MODE = 0b11 << 6;
+    }
+}
+
+bitflags! {
+    pub struct Control: u8 {
+        const BAUD_RATE = 0b1111;
+        const RX_CLOCK = 1 << 4;
+        const WORD_LENGTH = 0b11 << 5;
+        const STOP_BITS = 1 << 7;
+    }
+}
+
+/// Transmitter control field values
+const TX_OFF: u8 = 0b00 << 2;
+const TX_IRQ: u8 = 0b01 << 2;
+const TX_BREAK: u8 = 0b11 << 2;
+
+/// Parity bit sent with each character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Parity {
+    Odd,
+    Even,
+    /// Always 1, not checked
+    Mark,
+    /// Always 0, not checked
+    Space,
+}
+
+/// Word format of an emulated serial line.
+///
+/// Backends always carry 8 bits with no parity, so with shorter words the
+/// parity and stop bits are carried in the top bits of each host byte, where
+/// framing and parity errors are detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Format {
+    pub bits: u32,
+    pub parity: Option<Parity>,
+    pub stop_bits: u32,
+}
+
+impl Format {
+    /// Bits on the line per character, start and stop bits included.
+    pub fn frame_bits(&self) -> u32 {
+        1 + self.bits + self.parity.is_some() as u32 + self.stop_bits
+    }
+
+    fn parity_bit_(parity: Parity, data: u8) -> bool {
+        let ones = data.count_ones() % 2 == 1;
+        match parity {
+            Parity::Odd => !ones,
+            Parity::Even => ones,
+            Parity::Mark => true,
+            Parity::Space => false,
+        }
+    }
+
+    /// Host byte for a character.
+    pub fn encode(&self, data: u8) -> u8 {
+        if self.bits >= 8 {
+            return data;
+        }
+
+        let mut frame = data & ((1 << self.bits) - 1);
+        let mut next = self.bits;
+        if let Some(parity) = self.parity {
+            frame |= (Self::parity_bit_(parity, frame) as u8) << next;
+            next += 1;
+        }
+        // Stop bits and idle line are marks
+        if next < 8 {
+            frame |= 0xFF << next;
+        }
+        frame
+    }
+
+    /// Character for a host byte, and whether it has a parity or framing error.
+    pub fn decode(&self, byte: u8) -> (u8, bool, bool) {
+        if self.bits >= 8 {
+            return (byte, false, false);
+        }
+
+        let data = byte & ((1 << self.bits) - 1);
+        let mut parity_error = false;
+        let mut next = self.bits;
+        if let Some(parity) = self.parity {
+            let bit = byte & (1 << next) != 0;
+            let checked = matches!(parity, Parity::Odd | Parity::Even);
+            parity_error = checked && bit != Self::parity_bit_(parity, data);
+            next += 1;
+        }
+        let framing_error = next < 8 && byte & (1 << next) == 0;
+        (data, parity_error, framing_error)
     }
 }
 
-/// Simple ACIA serial device for 6502
+/// MOS 6551 ACIA
+///
+/// Characters take as long to shift in and out as they would at the programmed
+/// baud rate and word format, counted in CPU cycles. See `Format` for how
+/// short words are carried over the backend.
+///
+/// - $0: Transmit / receive data
+/// - $1: Status. Writing here is a programmed reset.
+/// - $2: Command
+/// - $3: Control
 pub struct Serial {
-    port: Box<dyn SerialPort>,
+    backend: Box<dyn Backend>,
+    cpu_clock: u64,
     status: Status,
+    command: Command,
+    control: Control,
+    /// Receive data register
+    rx_data: u8,
+    /// Transmit data register, waiting for the shift register
+    tx_data: Option<u8>,
+    /// Character being shifted out
+    tx_shift: Option<u8>,
+    /// Cycles until the transmitter finishes the current character
+    tx_cycles: u64,
+    /// Cycles until the receiver can take another character
+    rx_cycles: u64,
 }
 
 impl Serial {
-    pub fn new(path: &str) -> Result<Self, serialport::Error> {
-        let mut port = serialport::new(path, 19_200).open_native()?;
-        port.set_exclusive(false).expect("Could not set exclusive to false");
-        
-        Ok(Self {
-            port: Box::new(port),
-            status: Status::empty(),
-        })
+    pub fn new(backend: Box<dyn Backend>) -> Self {
+        Self {
+            backend,
+            cpu_clock: DEFAULT_CPU_CLOCK,
+            status: Status::TX_EMPTY,
+            command: Command::RX_IRQ_DISABLE,
+            control: Control::empty(),
+            rx_data: 0,
+            tx_data: None,
+            tx_shift: None,
+            tx_cycles: 0,
+            rx_cycles: 0,
+        }
+    }
+
+    /// An ACIA with nothing attached. Output is dropped and no input ever arrives.
+    pub fn disconnected() -> Self {
+        Self::new(Box::new(NullBackend))
+    }
+
+    fn format_(&self) -> Format {
+        let parity = if self.command.contains(Command::PARITY_ENABLE) {
+            Some(match (self.command & Command::PARITY_MODE).bits() >> 6 {
+                0b00 => Parity::Odd,
+                0b01 => Parity::Even,
+                0b10 => Parity::Mark,
+                _ => Parity::Space,
+            })
+        } else {
+            None
+        };
+
+        Format {
+            bits: 8 - ((self.control & Control::WORD_LENGTH).bits() >> 5) as u32,
+            parity,
+            stop_bits: if self.control.contains(Control::STOP_BITS) { 2 } else { 1 },
+        }
+    }
+
+    fn tx_control_(&self) -> u8 {
+        (self.command & Command::TX_CONTROL).bits()
+    }
+
+    /// The receiver and all interrupts are off while DTR is deasserted.
+    fn enabled_(&self) -> bool {
+        self.command.contains(Command::DTR)
+    }
+
+    /// CPU cycles per character, start and stop bits included.
+    fn char_cycles_(&self) -> u64 {
+        let baud = BAUD_RATES[(self.control & Control::BAUD_RATE).bits() as usize];
+        let bits = self.format_().frame_bits();
+        ((self.cpu_clock as f64 * bits as f64 / baud).round() as u64).max(1)
+    }
+
+    fn send_(&mut self, data: u8) {
+        let byte = self.format_().encode(data);
+        self.backend.write(byte);
+    }
+
+    /// Move the transmitter and receiver on by `cycles`.
+    fn clock_(&mut self, mut cycles: u64) {
+        // Transmitter
+        let mut tx_left = cycles;
+        loop {
+            if self.tx_shift.is_none() {
+                if self.tx_control_() == TX_OFF {
+                    break;
+                }
+                match self.tx_data.take() {
+                    Some(data) => {
+                        self.tx_shift = Some(data);
+                        self.tx_cycles = self.char_cycles_();
+                        self.status.insert(Status::TX_EMPTY);
+                        if self.enabled_() && self.tx_control_() == TX_IRQ {
+                            self.status.insert(Status::IRQ);
+                        }
+                    }
+                    None => break,
+                }
+            }
+
+            if self.tx_cycles > tx_left {
+                self.tx_cycles -= tx_left;
+                break;
+            }
+            tx_left -= self.tx_cycles;
+            self.tx_cycles = 0;
+            if let Some(data) = self.tx_shift.take() {
+                self.send_(data);
+            }
+        }
+
+        // Receiver. The backend is polled once per character time.
+        if !self.enabled_() {
+            return;
+        }
+        while cycles >= self.rx_cycles {
+            cycles -= self.rx_cycles;
+            self.rx_cycles = self.char_cycles_();
+            self.poll_modem_();
+
+            let byte = match self.backend.read() {
+                Some(byte) => byte,
+                None => break,
+            };
+            let (data, parity_error, framing_error) = self.format_().decode(byte);
+            let mut errors = Status::empty();
+            errors.set(Status::PARITY_ERROR, parity_error);
+            errors.set(Status::FRAMING_ERROR, framing_error);
+
+            if self.status.contains(Status::RX_FULL) {
+                // The new character is lost
+                self.status.insert(Status::OVERRUN);
+            } else {
+                self.rx_data = data;
+                self.status.remove(Status::PARITY_ERROR | Status::FRAMING_ERROR);
+                self.status.insert(Status::RX_FULL | errors);
+            }
+            if !self.command.contains(Command::RX_IRQ_DISABLE) {
+                self.status.insert(Status::IRQ);
+            }
+            if self.command.contains(Command::ECHO) && self.tx_control_() == TX_OFF {
+                self.send_(data);
+            }
+        }
+        self.rx_cycles -= cycles.min(self.rx_cycles);
+    }
+
+    /// Pick up DSR and DCD from the backend.
+    fn poll_modem_(&mut self) {
+        let dsr = self.backend.dsr();
+        let dcd = self.backend.dcd();
+        self.status.set(Status::DSR, !dsr);
+        self.status.set(Status::DCD, !dcd);
+    }
+
+    /// Drive DTR, RTS and break from the command register.
+    fn update_port_(&mut self) {
+        let tx_control = self.tx_control_();
+        self.backend.set_dtr(self.enabled_());
+        self.backend.set_rts(tx_control != TX_OFF);
+        self.backend.set_break(tx_control == TX_BREAK);
+    }
+
+    fn set_command_(&mut self, command: Command) {
+        let tx_irq = self.tx_control_() == TX_IRQ;
+        self.command = command;
+
+        // Enabling transmit interrupts with the transmitter already empty interrupts at once
+        if !tx_irq
+            && self.enabled_()
+            && self.tx_control_() == TX_IRQ
+            && self.status.contains(Status::TX_EMPTY)
+        {
+            self.status.insert(Status::IRQ);
+        }
+        self.update_port_();
+    }
+
+    fn set_control_(&mut self, control: Control) {
+        self.control = control;
+        let baud = BAUD_RATES[(control & Control::BAUD_RATE).bits() as usize];
+        self.backend.set_baud_rate(baud.round() as u32);
     }
 }
 
 impl IO for Serial {
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            ACIA_DATA => self.rx_data,
+            ACIA_STATUS => self.status.bits(),
+            ACIA_COMMAND => self.command.bits(),
+            ACIA_CONTROL => self.control.bits(),
+            _ => 0,
+        }
+    }
     fn read(&mut self, addr: u16) -> u8 {
+        let value = self.peek(addr);
         match addr {
-            ACIA_STATUS => {
-                let rx_full = self.port.bytes_to_read().map(|b| b > 0).unwrap_or_default();
-                let tx_empty = self.port.bytes_to_write().map(|b| b == 0).unwrap_or_default();
-                
-                self.status.set(Status::RX_FULL, rx_full);
-                self.status.set(Status::TX_EMPTY, tx_empty);
-
-                self.status.bits()
-            }
             ACIA_DATA => {
-                let mut buf = [0];
-                self.port.read(&mut buf).unwrap_or_default();
-                buf[0]
+                self.status.remove(Status::RX_FULL | Status::OVERRUN);
             }
-            _ => {
-                0
+            ACIA_STATUS => {
+                self.status.remove(Status::IRQ);
             }
+            _ => {}
         }
+        value
     }
     fn write(&mut self, addr: u16, data: u8) {
-        let buf: [u8; 1] = [data];
         match addr {
             ACIA_DATA => {
-                self.port.write(&buf).expect("Could not write to serial port");
-
+                // A character not yet picked up by the shift register is replaced
+                self.tx_data = Some(data);
+                self.status.remove(Status::TX_EMPTY);
             }
-            _ => {
-
+            ACIA_STATUS => {
+                // Programmed reset: the low command bits are cleared, the rest is untouched
+                self.status.remove(Status::OVERRUN);
+                let command = self.command & (Command::PARITY_ENABLE | Command::PARITY_MODE);
+                self.set_command_(command);
             }
+            ACIA_COMMAND => {
+                self.set_command_(Command::from_bits_truncate(data));
+            }
+            ACIA_CONTROL => {
+                self.set_control_(Control::from_bits_truncate(data));
+            }
+            _ => {}
         }
     }
+    fn tick(&mut self, cycles: u32) {
+        self.clock_(cycles as u64);
+    }
+    fn irq(&self) -> bool {
+        self.status.contains(Status::IRQ)
+    }
+}
+
+impl Device for Serial {
+    fn name(&self) -> &str {
+        "serial"
+    }
+
+    fn set_cpu_clock(&mut self, hz: u64) {
+        self.cpu_clock = hz;
+    }
 }
 
+/// Only the registers are saved. The backend belongs to the host.
+impl SaveState for Serial {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.status.bits());
+        w.u8(self.command.bits());
+        w.u8(self.control.bits());
+        w.u8(self.rx_data);
+        w.bool(self.tx_data.is_some());
+        w.u8(self.tx_data.unwrap_or_default());
+        w.bool(self.tx_shift.is_some());
+        w.u8(self.tx_shift.unwrap_or_default());
+        w.u64(self.tx_cycles);
+        w.u64(self.rx_cycles);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        if r.version() < 3 {
+            return Err(Error::new(ErrorKind::InvalidData, "save state is from an older version"));
+        }
+
+        self.status = Status::from_bits_truncate(r.u8()?);
+        self.command = Command::from_bits_truncate(r.u8()?);
+        self.control = Control::from_bits_truncate(r.u8()?);
+        self.rx_data = r.u8()?;
+        let tx_data = (r.bool()?, r.u8()?);
+        self.tx_data = if tx_data.0 { Some(tx_data.1) } else { None };
+        let tx_shift = (r.bool()?, r.u8()?);
+        self.tx_shift = if tx_shift.0 { Some(tx_shift.1) } else { None };
+        self.tx_cycles = r.u64()?;
+        self.rx_cycles = r.u64()?;
+        self.update_port_();
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
+};
+
+use crossterm::terminal;
+use serialport::{SerialPort, TTYPort};
+
+/// Byte that hangs up the stdio backend, as in telnet (Ctrl-])
+const STDIO_ESCAPE: u8 = 0x1D;
+
+/// Host side of an emulated serial line.
+///
+/// The ACIA calls `read` once per character time, so it must never block.
+/// Modem control lines are ignored by backends that don't have them,
+/// and their inputs read as asserted.
+pub trait Backend: Send {
+    /// Next byte from the host, if one has arrived.
+    fn read(&mut self) -> Option<u8>;
+
+    fn write(&mut self, byte: u8);
+
+    fn set_dtr(&mut self, _level: bool) {}
+
+    fn set_rts(&mut self, _level: bool) {}
+
+    fn set_break(&mut self, _on: bool) {}
+
+    fn set_baud_rate(&mut self, _baud: u32) {}
+
+    fn dsr(&mut self) -> bool {
+        true
+    }
+
+    fn dcd(&mut self) -> bool {
+        true
+    }
+}
+
+/// Nothing attached. Output is dropped and no input ever arrives.
+pub struct NullBackend;
+
+impl Backend for NullBackend {
+    fn read(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, _byte: u8) {}
+}
+
+/// A serial port on the host, such as `/dev/ttyUSB0`.
+pub struct TtyBackend {
+    port: TTYPort,
+}
+
+impl TtyBackend {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let mut port = serialport::new(path, 19_200).open_native()?;
+        port.set_exclusive(false)?;
+        Ok(Self { port })
+    }
+}
+
+impl Backend for TtyBackend {
+    fn read(&mut self) -> Option<u8> {
+        if self.port.bytes_to_read().unwrap_or_default() == 0 {
+            return None;
+        }
+
+        let mut buf = [0];
+        match self.port.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        let _ = self.port.write_all(&[byte]);
+    }
+
+    fn set_dtr(&mut self, level: bool) {
+        let _ = self.port.write_data_terminal_ready(level);
+    }
+
+    fn set_rts(&mut self, level: bool) {
+        let _ = self.port.write_request_to_send(level);
+    }
+
+    fn set_break(&mut self, on: bool) {
+        let _ = if on {
+            self.port.set_break()
+        } else {
+            self.port.clear_break()
+        };
+    }
+
+    fn set_baud_rate(&mut self, baud: u32) {
+        let _ = self.port.set_baud_rate(baud);
+    }
+
+    fn dsr(&mut self) -> bool {
+        self.port.read_data_set_ready().unwrap_or(true)
+    }
+
+    fn dcd(&mut self) -> bool {
+        self.port.read_carrier_detect().unwrap_or(true)
+    }
+}
+
+/// A new pseudo-terminal. Connect a terminal program to `path`.
+pub struct PtyBackend {
+    master: TtyBackend,
+    // Held open so the master keeps working while nothing is connected
+    slave: TTYPort,
+}
+
+impl PtyBackend {
+    pub fn open() -> io::Result<Self> {
+        let (master, slave) = TTYPort::pair()?;
+        Ok(Self {
+            master: TtyBackend { port: master },
+            slave,
+        })
+    }
+
+    /// Device for the other end, e.g. `/dev/pts/3`
+    pub fn path(&self) -> String {
+        self.slave.name().unwrap_or_default()
+    }
+}
+
+impl Backend for PtyBackend {
+    fn read(&mut self) -> Option<u8> {
+        self.master.read()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.master.write(byte)
+    }
+}
+
+/// Listens on a TCP port for one client at a time, e.g. `nc localhost 6502`.
+/// Bytes are passed through as they are, so use telnet in character mode.
+///
+/// Carrier detect follows whether a client is connected.
+/// Output is dropped while there isn't one.
+pub struct TcpBackend {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+}
+
+impl TcpBackend {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn accept_(&mut self) {
+        if self.client.is_some() {
+            return;
+        }
+        if let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                let _ = stream.set_nodelay(true);
+                self.client = Some(stream);
+            }
+        }
+    }
+}
+
+impl Backend for TcpBackend {
+    fn read(&mut self) -> Option<u8> {
+        self.accept_();
+        let client = self.client.as_mut()?;
+
+        let mut buf = [0];
+        match client.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => None,
+            // Hung up
+            _ => {
+                self.client = None;
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.accept_();
+        if let Some(client) = &mut self.client {
+            if let Err(err) = client.write_all(&[byte]) {
+                if err.kind() != io::ErrorKind::WouldBlock {
+                    self.client = None;
+                }
+            }
+        }
+    }
+
+    fn dcd(&mut self) -> bool {
+        self.accept_();
+        self.client.is_some()
+    }
+}
+
+/// The host terminal, in raw mode so keys go straight to the emulated machine.
+///
+/// Ctrl-C is passed on too, so Ctrl-] hangs up instead. The terminal is put
+/// back when the backend is dropped.
+pub struct StdioBackend {
+    input: Receiver<u8>,
+    hung_up: Arc<AtomicBool>,
+}
+
+impl StdioBackend {
+    pub fn open() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+
+        let (sender, input) = mpsc::channel();
+        let hung_up = Arc::new(AtomicBool::new(false));
+        let hangup = hung_up.clone();
+        thread::spawn(move || {
+            let mut stdin = io::stdin();
+            let mut buf = [0];
+            while let Ok(1) = stdin.read(&mut buf) {
+                if buf[0] == STDIO_ESCAPE || sender.send(buf[0]).is_err() {
+                    break;
+                }
+            }
+            hangup.store(true, Ordering::SeqCst);
+        });
+
+        Ok(Self { input, hung_up })
+    }
+
+    /// Set once the user hangs up with Ctrl-] or stdin closes.
+    pub fn hung_up(&self) -> Arc<AtomicBool> {
+        self.hung_up.clone()
+    }
+}
+
+impl Backend for StdioBackend {
+    fn read(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn write(&mut self, byte: u8) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&[byte]);
+        let _ = stdout.flush();
+    }
+
+    fn dcd(&mut self) -> bool {
+        !self.hung_up.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for StdioBackend {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Input played back from a buffer, such as the contents of a file,
+/// with output going to any writer.
+pub struct FileBackend {
+    input: VecDeque<u8>,
+    output: Box<dyn Write + Send>,
+}
+
+impl FileBackend {
+    pub fn new<W: Write + Send + 'static>(input: Vec<u8>, output: W) -> Self {
+        Self {
+            input: input.into(),
+            output: Box::new(output),
+        }
+    }
+}
+
+impl Backend for FileBackend {
+    fn read(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn write(&mut self, byte: u8) {
+        let _ = self.output.write_all(&[byte]);
+        let _ = self.output.flush();
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use super::backend::Backend;
+
+/// Where a script gets the time from, so tests can control it.
+pub type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// How long an `expect` waits unless the script says otherwise
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Characters per second sent unless the script says otherwise
+const DEFAULT_RATE: f64 = 100.0;
+
+/// One line of a script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// Type these bytes into the receive side
+    Send(Vec<u8>),
+    /// Wait for these bytes to come out of the transmit side
+    Expect(Vec<u8>),
+    /// Do nothing for a while
+    Delay(Duration),
+    /// How long the following `expect`s wait
+    Timeout(Duration),
+    /// Characters per second for the following `send`s, or 0 for back to
+    /// back at the line rate
+    Rate(f64),
+}
+
+/// A conversation with the emulated machine over its serial port.
+///
+/// One command per line, with strings in double quotes:
+///
+/// ```text
+/// # Boot minmon into BASIC
+/// timeout 5
+/// expect "Cold [C] or warm [W] start?"
+/// send "C"
+/// expect "Memory size?"
+/// sendline ""
+/// rate 30
+/// sendline "10 PRINT \"HELLO\""
+/// delay 0.5
+/// ```
+///
+/// `sendline` adds a carriage return, as a terminal would. Strings take
+/// `\r`, `\n`, `\t`, `\\`, `\"` and `\xNN` escapes. Times are in seconds.
+///
+/// There is no flow control, as with a real terminal. With `rate 0`, or any
+/// rate faster than the firmware reads, characters it doesn't collect within
+/// a character time are lost to an overrun.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Script {
+    pub steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut steps = vec![];
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (command, arg) = match line.find(char::is_whitespace) {
+                Some(split) => (&line[..split], line[split..].trim()),
+                None => (line, ""),
+            };
+            let error = |message: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, message),
+                )
+            };
+            let string = || parse_string_(arg).ok_or_else(|| error("expected a quoted string"));
+            let number = |what: &str| {
+                arg.parse::<f64>()
+                    .ok()
+                    .filter(|n| n.is_finite() && *n >= 0.0)
+                    .ok_or_else(|| error(what))
+            };
+            let seconds = || number("expected a number of seconds");
+
+            steps.push(match command {
+                "send" => Step::Send(string()?),
+                "sendline" => {
+                    let mut bytes = string()?;
+                    bytes.push(b'\r');
+                    Step::Send(bytes)
+                }
+                "expect" => Step::Expect(string()?),
+                "delay" => Step::Delay(Duration::from_secs_f64(seconds()?)),
+                "timeout" => Step::Timeout(Duration::from_secs_f64(seconds()?)),
+                "rate" => Step::Rate(number("expected characters per second")?),
+                _ => return Err(error(&format!("unknown command {:?}", command))),
+            });
+        }
+        Ok(Self { steps })
+    }
+}
+
+/// A double-quoted string with escapes, and nothing after it.
+fn parse_string_(s: &str) -> Option<Vec<u8>> {
+    let s = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut bytes = vec![];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return None,
+            '\\' => match chars.next()? {
+                'r' => bytes.push(b'\r'),
+                'n' => bytes.push(b'\n'),
+                't' => bytes.push(b'\t'),
+                '\\' => bytes.push(b'\\'),
+                '"' => bytes.push(b'"'),
+                'x' => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+                }
+                _ => return None,
+            },
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    Some(bytes)
+}
+
+/// How far a script has got.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Running,
+    /// Every step completed
+    Passed,
+    Failed(String),
+}
+
+/// Shared between the backend and whoever is waiting for the script to finish.
+struct Progress {
+    outcome: Outcome,
+    /// When the current `expect` gives up
+    deadline: Option<Instant>,
+    expecting: Vec<u8>,
+}
+
+impl Progress {
+    fn check_deadline_(&mut self, now: Instant) {
+        if self.outcome != Outcome::Running {
+            return;
+        }
+        if let Some(deadline) = self.deadline {
+            if now >= deadline {
+                self.outcome = Outcome::Failed(format!(
+                    "timed out waiting for {:?}",
+                    String::from_utf8_lossy(&self.expecting)
+                ));
+            }
+        }
+    }
+}
+
+/// Watches a running script from another thread.
+#[derive(Clone)]
+pub struct ScriptStatus {
+    progress: Arc<Mutex<Progress>>,
+    clock: Clock,
+}
+
+impl ScriptStatus {
+    /// Current outcome. An `expect` that has run out of time fails here even
+    /// if the machine has stopped polling the serial port.
+    pub fn outcome(&self) -> Outcome {
+        let mut progress = self.progress.lock();
+        progress.check_deadline_((self.clock)());
+        progress.outcome.clone()
+    }
+
+    /// What the script is waiting to see, if anything.
+    pub fn expecting(&self) -> Option<Vec<u8>> {
+        let progress = self.progress.lock();
+        progress.deadline.map(|_| progress.expecting.clone())
+    }
+}
+
+/// Plays a `Script` against the emulated machine, like `expect`.
+///
+/// Sends are paced in wall-clock time, so scripts behave the same whatever
+/// speed the emulator runs at. Everything the machine transmits is copied
+/// to `output` as a transcript.
+pub struct ScriptBackend {
+    steps: VecDeque<Step>,
+    status: ScriptStatus,
+    output: Box<dyn Write + Send>,
+    /// Bytes of the current `send` still to go
+    sending: VecDeque<u8>,
+    /// Transmitted since the last match
+    seen: Vec<u8>,
+    /// End of the current `delay`
+    resume_at: Instant,
+    /// Earliest time the next character can go
+    next_char_at: Instant,
+    timeout: Duration,
+    char_delay: Duration,
+}
+
+impl ScriptBackend {
+    pub fn new<W: Write + Send + 'static>(script: Script, output: W) -> Self {
+        Self::with_clock(script, output, Arc::new(Instant::now))
+    }
+
+    /// Like `new`, but reading the time from `clock` rather than the system.
+    pub fn with_clock<W: Write + Send + 'static>(script: Script, output: W, clock: Clock) -> Self {
+        let now = clock();
+        let progress = Progress {
+            outcome: Outcome::Running,
+            deadline: None,
+            expecting: vec![],
+        };
+        let mut backend = Self {
+            steps: script.steps.into(),
+            status: ScriptStatus {
+                progress: Arc::new(Mutex::new(progress)),
+                clock,
+            },
+            output: Box::new(output),
+            sending: VecDeque::new(),
+            seen: vec![],
+            resume_at: now,
+            next_char_at: now,
+            timeout: DEFAULT_TIMEOUT,
+            char_delay: Duration::from_secs_f64(1.0 / DEFAULT_RATE),
+        };
+        backend.advance_(now);
+        backend
+    }
+
+    fn now_(&self) -> Instant {
+        (self.status.clock)()
+    }
+
+    pub fn status(&self) -> ScriptStatus {
+        self.status.clone()
+    }
+
+    /// Run steps until one has to wait.
+    fn advance_(&mut self, now: Instant) {
+        let mut progress = self.status.progress.lock();
+        progress.check_deadline_(now);
+        if progress.outcome != Outcome::Running {
+            return;
+        }
+
+        loop {
+            if !self.sending.is_empty() || now < self.resume_at {
+                return;
+            }
+
+            if progress.deadline.is_some() {
+                let expecting = &progress.expecting;
+                let found = if expecting.is_empty() {
+                    Some(0)
+                } else {
+                    self.seen
+                        .windows(expecting.len())
+                        .position(|window| window == &expecting[..])
+                };
+                match found {
+                    Some(start) => {
+                        self.seen.drain(..start + expecting.len());
+                        progress.deadline = None;
+                    }
+                    None => return,
+                }
+            }
+
+            match self.steps.pop_front() {
+                Some(Step::Send(bytes)) => self.sending.extend(bytes),
+                Some(Step::Expect(bytes)) => {
+                    progress.expecting = bytes;
+                    progress.deadline = Some(now + self.timeout);
+                }
+                Some(Step::Delay(delay)) => self.resume_at = now + delay,
+                Some(Step::Timeout(timeout)) => self.timeout = timeout,
+                Some(Step::Rate(rate)) => {
+                    self.char_delay = if rate > 0.0 {
+                        Duration::from_secs_f64(1.0 / rate)
+                    } else {
+                        Duration::ZERO
+                    };
+                }
+                None => {
+                    progress.outcome = Outcome::Passed;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Backend for ScriptBackend {
+    fn read(&mut self) -> Option<u8> {
+        let now = self.now_();
+        self.advance_(now);
+        if now < self.next_char_at {
+            return None;
+        }
+
+        let byte = self.sending.pop_front()?;
+        self.next_char_at = now + self.char_delay;
+        if self.sending.is_empty() {
+            self.advance_(now);
+        }
+        Some(byte)
+    }
+
+    fn write(&mut self, byte: u8) {
+        let _ = self.output.write_all(&[byte]);
+        let _ = self.output.flush();
+        self.seen.push(byte);
+        self.advance_(self.now_());
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{
+    bus::Bus,
+    cpu::{Status, CPU6502},
+};
+
+/// Copy of the machine state for display, published by the run thread
+/// so the UI never has to wait for the CPU lock.
+pub struct Snapshot {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub p: Status,
+    pub cycles: u64,
+    pub instructions: usize,
+    /// The whole address space, devices included
+    pub memory: Vec<u8>,
+    /// Number of instructions that can be stepped back
+    pub history_len: usize,
+}
+
+impl Snapshot {
+    pub fn capture(cpu: &CPU6502<Bus>, history_len: usize) -> Self {
+        Self {
+            pc: cpu.pc,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            sp: cpu.sp,
+            p: cpu.p,
+            cycles: cpu.cycles,
+            instructions: cpu.instructions,
+            memory: cpu.mem.peek_all(),
+            history_len,
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{self, Error, ErrorKind};
+
+/// Identifies a save state file.
+pub const MAGIC: &[u8; 4] = b"6502";
+
+/// Save state format version.
+///
+/// Bump this whenever the layout written by any `SaveState` implementation changes.
+pub const VERSION: u16 = 3;
+
+/// Save and restore the complete state of a component.
+///
+/// States are written field by field in a fixed order, so `load_state`
+/// must read back exactly what `save_state` wrote.
+pub trait SaveState {
+    fn save_state(&self, w: &mut StateWriter);
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()>;
+}
+
+/// Builds a versioned save state. Values are little-endian.
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        Self { buf }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Length-prefixed byte string
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.buf.extend_from_slice(data);
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads a save state written by `StateWriter`.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// Check the header and start reading the state.
+    pub fn new(data: &'a [u8]) -> io::Result<Self> {
+        if data.len() < 6 || &data[0..4] != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a save state"));
+        }
+
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version == 0 || version > VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported save state version {}", version),
+            ));
+        }
+
+        Ok(Self {
+            data,
+            pos: 6,
+            version,
+        })
+    }
+
+    /// Format version of the state being read.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "save state is truncated",
+            ));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Length-prefixed byte string
+    pub fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Length-prefixed byte string that must fill `buf` exactly
+    pub fn bytes_into(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let bytes = self.bytes()?;
+        if bytes.len() != buf.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "save state size mismatch",
+            ));
+        }
+
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Fail if anything is left over, which means the layout doesn't match.
+    pub fn finish(self) -> io::Result<()> {
+        if self.pos != self.data.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "unexpected data at end of save state",
+            ));
+        }
+        Ok(())
+    }
+}
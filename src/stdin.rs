@@ -1,6 +1,10 @@
 use std::io::Read;
 
-use crate::io::IO;
+use crate::{
+    device::Device,
+    io::IO,
+    state::{SaveState, StateReader, StateWriter},
+};
 
 /// Simple stdin device for 6502
 pub struct Stdin {
@@ -19,6 +23,9 @@ impl IO for Stdin {
     fn read(&mut self, addr: u16) -> u8 {
         self.buffer[addr as usize]
     }
+    fn peek(&self, addr: u16) -> u8 {
+        self.buffer[addr as usize]
+    }
     fn write(&mut self, _addr: u16, _data: u8) {
         let mut buf = "".to_string(); 
         let _ = std::io::stdin().read_line(&mut buf);
@@ -28,3 +35,19 @@ impl IO for Stdin {
         }
     }
 }
+
+impl Device for Stdin {
+    fn name(&self) -> &str {
+        "stdin"
+    }
+}
+
+impl SaveState for Stdin {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.buffer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.bytes_into(&mut self.buffer)
+    }
+}
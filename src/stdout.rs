@@ -1,5 +1,9 @@
-use crate::io::IO;
-use std::io::Write;
+use crate::{
+    device::Device,
+    io::IO,
+    state::{SaveState, StateReader, StateWriter},
+};
+use std::io::{self, Write};
 
 /// Simple stdout device for 6502
 pub struct Stdout {
@@ -16,7 +20,7 @@ impl Stdout {
     }
 
     pub fn flush(&mut self) {
-        let _ = std::io::stdout().write(&self.buffer);
+        let _ = std::io::stdout().write(&self.buffer[..self.pos]);
         self.buffer = [0; 4096];
         self.pos = 0;
     }
@@ -26,8 +30,33 @@ impl IO for Stdout {
     fn read(&mut self, _addr: u16) -> u8 {
         0
     }
+    fn peek(&self, _addr: u16) -> u8 {
+        0
+    }
     fn write(&mut self, _addr: u16, data: u8) {
         self.buffer[self.pos] = data;
         self.pos += 1;
+        if self.pos == self.buffer.len() {
+            self.flush();
+        }
+    }
+}
+
+impl Device for Stdout {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+}
+
+impl SaveState for Stdout {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.buffer);
+        w.u32(self.pos as u32);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into(&mut self.buffer)?;
+        self.pos = (r.u32()? as usize).min(self.buffer.len());
+        Ok(())
     }
 }
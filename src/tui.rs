@@ -1,4 +1,4 @@
-use std::{borrow::BorrowMut, io::stdout};
+use std::{borrow::BorrowMut, io::stdout, path::PathBuf};
 
 use crossterm::{
     event::{self, Event, KeyCode},
@@ -11,15 +11,27 @@ use crate::{cpu::Status, debugger::Debugger};
 
 pub struct Tui {
     debugger: Debugger,
+    /// Where F5 saves and F9 loads the machine state
+    pub state_file: PathBuf,
+    /// Shown on the status line until the next key press
+    pub message: Option<String>,
 }
 
 impl Tui {
     pub fn new(debugger: Debugger) -> Self {
-        Self { debugger }
+        Self {
+            debugger,
+            state_file: PathBuf::from("6502.state"),
+            message: None,
+        }
     }
 
     pub fn show(&mut self) -> std::io::Result<()> {
         let mut d = &mut self.debugger;
+        let state_file = &self.state_file;
+        let mut message: Option<String> = self.message.take();
+        // Cycle number being typed after pressing [c]
+        let mut rewind_prompt: Option<String> = None;
 
         enable_raw_mode()?;
         stdout().execute(EnterAlternateScreen)?;
@@ -29,7 +41,7 @@ impl Tui {
         // d.show();
 
         loop {
-            let cpu = d.cpu.lock();
+            let cpu = d.snapshot();
             // d.flush(&    self.mem.lock().unwrap().0[0x200..=0x5FF]);
 
             terminal.draw(|frame: &mut Frame| {
@@ -115,8 +127,29 @@ impl Tui {
                         } else {
                             "o   ".dim()
                         },
+                        "[F5]".bold(),
+                        " save   ".dim(),
+                        "[F9]".bold(),
+                        " load   ".dim(),
                         "[q]".bold(),
                         "uit".dim(),
+                        match (&message, d.halt_reason()) {
+                            (Some(message), _) => format!("   {}", message).light_yellow(),
+                            (None, Some(reason)) => format!("   Halted: {}", reason).light_red(),
+                            (None, None) => "".into(),
+                        },
+                    ]),
+                    Line::from(vec![
+                        "[b]".bold(),
+                        "ack   ".dim(),
+                        "[B]".bold(),
+                        "ack to breakpoint   ".dim(),
+                        "[c]".bold(),
+                        "ycle rewind   ".dim(),
+                        match &rewind_prompt {
+                            Some(input) => format!("Rewind to cycle: {}_", input).light_yellow(),
+                            None => format!("{} steps back available", cpu.history_len).dim(),
+                        },
                     ]),
                 ]))
                 .block(Block::default().padding(Padding::horizontal(1)));
@@ -203,7 +236,7 @@ impl Tui {
                 let mut stack_lines = vec![];
 
                 // Stack (ascending order so reverse from actual memory layout)
-                for (idx, byte) in cpu.mem.mem.0[0x100..=0x1FF].iter().rev().enumerate() {
+                for (idx, byte) in cpu.memory[0x100..=0x1FF].iter().rev().enumerate() {
                     let addr = 0xff - idx;
                     let byte_text = format!("{:02X}", byte);
                     stack_lines.push(Line::from(vec![
@@ -247,9 +280,7 @@ impl Tui {
 
                 // Display memory as rows of 8 bytes indexed by address
                 let mem_text = Text::from(
-                    cpu.mem
-                        .mem
-                        .0
+                    cpu.memory
                         .chunks(8)
                         .into_iter()
                         .enumerate()
@@ -297,7 +328,29 @@ impl Tui {
             drop(cpu);
             if event::poll(std::time::Duration::from_millis(50))? {
                 if let Event::Key(key) = event::read()? {
-                    if key.kind == event::KeyEventKind::Press && key.code == KeyCode::Char('q') {
+                    if key.kind == event::KeyEventKind::Press {
+                        message = None;
+                    }
+
+                    if let (Some(input), event::KeyEventKind::Press) = (&mut rewind_prompt, key.kind) {
+                        match key.code {
+                            KeyCode::Char(ch) if ch.is_ascii_digit() => input.push(ch),
+                            KeyCode::Backspace => {
+                                input.pop();
+                            }
+                            KeyCode::Enter => {
+                                if let Ok(cycle) = input.parse::<u64>() {
+                                    d.pause();
+                                    if !d.rewind_to(cycle) {
+                                        message = Some("History doesn't reach that far back".to_string());
+                                    }
+                                }
+                                rewind_prompt = None;
+                            }
+                            KeyCode::Esc => rewind_prompt = None,
+                            _ => {}
+                        }
+                    } else if key.kind == event::KeyEventKind::Press && key.code == KeyCode::Char('q') {
                         break;
                     } else if key.kind == event::KeyEventKind::Press
                         && key.code == KeyCode::Char('n')
@@ -315,6 +368,34 @@ impl Tui {
                         && key.code == KeyCode::Char('s')
                     {
                         d.pause();
+                    } else if key.kind == event::KeyEventKind::Press
+                        && key.code == KeyCode::Char('b')
+                    {
+                        d.pause();
+                        if !d.step_back() {
+                            message = Some("No more history".to_string());
+                        }
+                    } else if key.kind == event::KeyEventKind::Press
+                        && key.code == KeyCode::Char('B')
+                    {
+                        d.pause();
+                        if d.run_back().is_none() {
+                            message = Some("No earlier breakpoint in history".to_string());
+                        }
+                    } else if key.kind == event::KeyEventKind::Press
+                        && key.code == KeyCode::Char('c')
+                    {
+                        rewind_prompt = Some(String::new());
+                    } else if key.kind == event::KeyEventKind::Press && key.code == KeyCode::F(5) {
+                        message = Some(match d.save_state(state_file) {
+                            Ok(()) => format!("Saved state to {}", state_file.display()),
+                            Err(err) => format!("Could not save state: {}", err),
+                        });
+                    } else if key.kind == event::KeyEventKind::Press && key.code == KeyCode::F(9) {
+                        message = Some(match d.load_state(state_file) {
+                            Ok(()) => format!("Loaded state from {}", state_file.display()),
+                            Err(err) => format!("Could not load state: {}", err),
+                        });
                     }
                 }
             }
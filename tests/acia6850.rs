@@ -0,0 +1,96 @@
+//! The startup and serial I/O routines from `src/asm/minmon.asm`, talking to
+//! a 6850 ACIA mirrored across $A000-$BFFF as on Grant Searle's SBC, and the
+//! ACIA's timing at the edges of its clock settings.
+
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+
+use nes::{
+    acia6850::{Acia6850, Status},
+    cpu::HaltReason,
+    device::{Device, Mapping},
+    io::IO,
+    machine::Machine,
+    serial::backend::TtyBackend,
+};
+use serialport::{SerialPort, TTYPort};
+
+const MAX_CYCLES: u64 = 10_000_000;
+
+const MESSAGE: &[u8] = b"\x0CCold [C] or warm [W] start?\r\n";
+
+/// minmon.asm assembled for $FF00-$FFFF, with BASIC's entry points at
+/// `COLD_START` and `RESTART`. See tests/roms/README.md.
+const MINMON: &[u8] = include_bytes!("roms/minmon.bin");
+const COLD_START: u16 = 0xFE00;
+const RESTART: u16 = 0xFE03;
+
+/// Boot minmon with `key` typed ahead, and return where it ended up and what
+/// it printed.
+fn boot(key: u8) -> (HaltReason, Vec<u8>) {
+    let (mut host, device) = TTYPort::pair().unwrap();
+    host.set_timeout(Duration::from_millis(200)).unwrap();
+    let acia = Acia6850::new(Box::new(TtyBackend::open(&device.name().unwrap()).unwrap()));
+
+    let mut machine = Machine::builder()
+        .serial_at(None)
+        .device(acia, vec![Mapping::new(0xA000..=0xBFFF).mirrored(2)])
+        .image(MINMON, 0xFF00)
+        // BASIC stands in as a loop at each entry point
+        .image(&[0x4C, 0x00, 0xFE, 0x4C, 0x03, 0xFE], COLD_START)
+        .build();
+    assert_eq!(machine.cpu.pc, 0xFF00, "reset vector");
+
+    // Typed ahead, so it waits in the receive register until the prompt is out
+    host.write_all(&[key]).unwrap();
+
+    let reason = machine.run(|cpu| match cpu.halt_reason() {
+        None if cpu.cycles >= MAX_CYCLES => Some(HaltReason::CycleLimit(cpu.cycles)),
+        reason => reason,
+    });
+
+    // Let the last character shift out
+    machine.run_for_cycles(1000);
+    let mut prompt = vec![0; MESSAGE.len()];
+    host.read_exact(&mut prompt).unwrap();
+    (reason, prompt)
+}
+
+#[test]
+fn minmon_cold_start() {
+    let (reason, prompt) = boot(b'c');
+    assert_eq!(reason, HaltReason::InfiniteLoop(COLD_START));
+    assert_eq!(prompt, MESSAGE);
+}
+
+#[test]
+fn minmon_warm_start() {
+    let (reason, prompt) = boot(b'W');
+    assert_eq!(reason, HaltReason::InfiniteLoop(RESTART));
+    assert_eq!(prompt, MESSAGE);
+}
+
+#[test]
+fn fast_cpu_clock() {
+    let mut acia = Acia6850::disconnected();
+    acia.set_cpu_clock(u64::MAX);
+    // 8N1, clock divided by 16
+    acia.write(0, 0x15);
+
+    // The first character goes straight to the shift register, where it
+    // takes longer than any tick to send
+    acia.write(1, b'A');
+    acia.tick(u32::MAX);
+    assert!(Status::from_bits_truncate(acia.read(0)).contains(Status::TX_EMPTY));
+    acia.write(1, b'B');
+    acia.tick(u32::MAX);
+    assert!(!Status::from_bits_truncate(acia.read(0)).contains(Status::TX_EMPTY));
+}
+
+#[test]
+#[should_panic(expected = "0 Hz")]
+fn zero_clock() {
+    let _ = Acia6850::disconnected().with_clock(0);
+}
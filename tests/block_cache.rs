@@ -0,0 +1,261 @@
+//! The block cache must give the same results as the reference interpreter,
+//! including when code modifies itself or the memory map changes under it.
+
+use std::{fs, path::Path};
+
+use nes::{
+    cpu::{HaltReason, Variant, CPU6502},
+    io::IO,
+    mem::Memory,
+};
+
+const MAX_CYCLES: u64 = 200_000_000;
+
+/// Run until the program traps in a loop, returning the trap address.
+fn run_to_trap<T: IO>(cpu: &mut CPU6502<T>) -> u16 {
+    let reason = cpu.run_until(|cpu| match cpu.halt_reason() {
+        None if cpu.cycles >= MAX_CYCLES => Some(HaltReason::CycleLimit(cpu.cycles)),
+        reason => reason,
+    });
+
+    match reason {
+        HaltReason::InfiniteLoop(addr) => addr,
+        reason => panic!("unexpected halt: {}", reason),
+    }
+}
+
+/// Run a copy of the machine on each engine and check they end up the same.
+fn compare<T: IO>(
+    new_cpu: impl Fn() -> CPU6502<T>,
+    memory: impl Fn(&mut T) -> Vec<u8>,
+) -> CPU6502<T> {
+    let mut reference = new_cpu();
+    let mut cached = new_cpu();
+    cached.block_cache = true;
+
+    let reference_trap = run_to_trap(&mut reference);
+    let cached_trap = run_to_trap(&mut cached);
+
+    assert_eq!(cached_trap, reference_trap, "trap address");
+    assert_eq!(
+        (cached.a, cached.x, cached.y, cached.sp, cached.p),
+        (
+            reference.a,
+            reference.x,
+            reference.y,
+            reference.sp,
+            reference.p
+        ),
+        "registers"
+    );
+    assert_eq!(cached.cycles, reference.cycles, "cycles");
+    assert_eq!(cached.instructions, reference.instructions, "instructions");
+    assert!(
+        memory(&mut cached.mem) == memory(&mut reference.mem),
+        "memory differs"
+    );
+    cached
+}
+
+fn ram(rom: &[u8], offset: u16, variant: Variant) -> CPU6502<Memory> {
+    let mut mem = Memory::new();
+    mem.load(rom, offset);
+
+    let mut cpu = CPU6502::new(mem);
+    cpu.variant = variant;
+    cpu.reset();
+    cpu.pc = 0x0200;
+    cpu
+}
+
+#[test]
+fn decimal_tests_match_reference() {
+    let roms = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("roms");
+
+    for (name, variant) in [
+        ("6502_decimal_test.bin", Variant::NMOS6502),
+        ("65c02_decimal_test.bin", Variant::WDC65C02),
+    ]
+    .iter()
+    {
+        let rom = fs::read(roms.join(name)).unwrap();
+        compare(|| ram(&rom, 0x0200, *variant), |mem| mem.0.to_vec());
+    }
+}
+
+#[test]
+fn self_modifying_code() {
+    #[rustfmt::skip]
+    let program = [
+        0xA2, 0x00,       // 0200 LDX #0
+        0xA9, 0x01,       // 0202 LDA #1      operand incremented every pass
+        0x18,             // 0204 CLC
+        0x6D, 0x00, 0x03, // 0205 ADC $0300
+        0x8D, 0x00, 0x03, // 0208 STA $0300
+        0xEE, 0x03, 0x02, // 020B INC $0203
+        0xE8,             // 020E INX
+        0xE0, 0x10,       // 020F CPX #$10
+        0xD0, 0xEF,       // 0211 BNE $0202
+        0xA9, 0xE8,       // 0213 LDA #$E8
+        0x8D, 0x18, 0x02, // 0215 STA $0218   patch the next instruction in this block
+        0xEA,             // 0218 NOP         becomes INX
+        0x4C, 0x19, 0x02, // 0219 JMP $0219
+    ];
+
+    let cpu = compare(
+        || ram(&program, 0x0200, Variant::NMOS6502),
+        |mem| mem.0.to_vec(),
+    );
+
+    // 1 + 2 + ... + 16
+    assert_eq!(cpu.mem.0[0x0300], 136);
+    assert_eq!(cpu.x, 0x11);
+}
+
+#[test]
+fn cmos_nops() {
+    // The 65C02 pads its NOPs, documented and not, with extra reads.
+    #[rustfmt::skip]
+    let program = [
+        0xEA,             // 0200 NOP
+        0x03,             // 0201 NOP         1 byte, 1 cycle
+        0x02, 0x00,       // 0202 NOP #0
+        0x44, 0x10,       // 0204 NOP $10
+        0x54, 0x10,       // 0206 NOP $10,X
+        0x5C, 0x34, 0x12, // 0208 NOP $1234   8 cycles
+        0xDC, 0x34, 0x12, // 020B NOP $1234
+        0xA9, 0x01,       // 020E LDA #1
+        0x4C, 0x10, 0x02, // 0210 JMP $0210
+    ];
+
+    let cpu = compare(
+        || ram(&program, 0x0200, Variant::WDC65C02),
+        |mem| mem.0.to_vec(),
+    );
+    assert_eq!(cpu.a, 1);
+}
+
+/// RAM below $8000 and two switchable banks above it.
+/// Writing to $7FFF selects the bank.
+struct Banked {
+    ram: Vec<u8>,
+    banks: [Vec<u8>; 2],
+    bank: usize,
+    generation: u64,
+}
+
+impl IO for Banked {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => self.banks[self.bank][addr as usize - 0x8000],
+            _ => self.ram[addr as usize],
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x7FFF => {
+                self.bank = data as usize & 1;
+                self.generation += 1;
+            }
+            0x8000..=0xFFFF => {}
+            _ => self.ram[addr as usize] = data,
+        }
+    }
+
+    fn map_generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+#[test]
+fn bank_switching() {
+    #[rustfmt::skip]
+    let program = [
+        0x20, 0x00, 0x80, // 0200 JSR $8000
+        0x85, 0x10,       // 0203 STA $10
+        0xA9, 0x01,       // 0205 LDA #1
+        0x8D, 0xFF, 0x7F, // 0207 STA $7FFF   switch to bank 1
+        0x20, 0x00, 0x80, // 020A JSR $8000
+        0x85, 0x11,       // 020D STA $11
+        0x4C, 0x0F, 0x02, // 020F JMP $020F
+    ];
+
+    let new_cpu = || {
+        let mut ram = vec![0; 0x8000];
+        ram[0x0200..0x0200 + program.len()].copy_from_slice(&program);
+
+        let mut banks = [vec![0; 0x8000], vec![0; 0x8000]];
+        banks[0][..3].copy_from_slice(&[0xA9, 0xAA, 0x60]); // LDA #$AA, RTS
+        banks[1][..4].copy_from_slice(&[0xEA, 0xA9, 0xBB, 0x60]); // NOP, LDA #$BB, RTS
+
+        let mut cpu = CPU6502::new(Banked {
+            ram,
+            banks,
+            bank: 0,
+            generation: 0,
+        });
+        cpu.reset();
+        cpu.pc = 0x0200;
+        cpu
+    };
+
+    let cpu = compare(new_cpu, |mem| mem.ram.clone());
+    assert_eq!(cpu.mem.ram[0x10], 0xAA);
+    assert_eq!(cpu.mem.ram[0x11], 0xBB);
+}
+
+/// RAM with a receive register at $D000. Reading it takes the byte and
+/// counts the read, as reading an ACIA's data register would.
+struct Receiver {
+    ram: Vec<u8>,
+    reads: u32,
+}
+
+impl IO for Receiver {
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr == 0xD000 {
+            self.reads += 1;
+        }
+        self.peek(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.ram[addr as usize] = data;
+    }
+}
+
+#[test]
+fn decoding_ahead_leaves_devices_alone() {
+    // The block is decoded as far as $D000 before the STA turns the NOP
+    // into a trap, so only the decoder ever looks at the receive register.
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x4C,       // CFF8 LDA #$4C
+        0x8D, 0xFD, 0xCF, // CFFA STA $CFFD
+        0xEA,             // CFFD NOP         becomes JMP $CFFD
+        0xFD, 0xCF,       // CFFE             trap address, SBC $xxCF,X until then
+    ];
+    let new_cpu = || {
+        let mut ram = vec![0; 0x10000];
+        ram[0xCFF8..0xD000].copy_from_slice(&program);
+
+        let mut cpu = CPU6502::new(Receiver { ram, reads: 0 });
+        cpu.reset();
+        cpu.pc = 0xCFF8;
+        cpu
+    };
+
+    let cpu = compare(new_cpu, |mem| mem.ram.clone());
+    assert_eq!(cpu.mem.reads, 0, "receive register read");
+}
@@ -0,0 +1,144 @@
+//! The bus's device registry: which device answers an address, the address
+//! it sees, and save states only loading into the same set of devices.
+
+use std::io;
+
+use nes::{
+    bus::Bus,
+    device::{Device, Mapping},
+    display::Display,
+    io::IO,
+    mem::Memory,
+    state::{SaveState, StateReader, StateWriter},
+};
+
+/// Reads return a tag identifying the device, or with `echo` the address
+/// the device sees.
+struct Latch {
+    name: &'static str,
+    tag: u8,
+    echo: bool,
+}
+
+fn latch(name: &'static str, tag: u8) -> Box<Latch> {
+    Box::new(Latch {
+        name,
+        tag,
+        echo: false,
+    })
+}
+
+impl IO for Latch {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        if self.echo {
+            addr as u8
+        } else {
+            self.tag
+        }
+    }
+
+    fn write(&mut self, _addr: u16, _data: u8) {}
+}
+
+impl SaveState for Latch {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.tag);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.tag = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Device for Latch {
+    fn name(&self) -> &str {
+        self.name
+    }
+}
+
+#[test]
+fn first_mapped_wins() {
+    let mut bus = Bus::new(Memory::new());
+    let wide = bus.attach(latch("wide", 1));
+    let narrow = bus.attach(latch("narrow", 2));
+    bus.map(wide, Mapping::new(0xD000..=0xD0FF));
+    bus.map(narrow, Mapping::new(0xD000..=0xD00F));
+    bus.map(narrow, Mapping::new(0xE000..=0xE00F));
+
+    assert_eq!(bus.read(0xD005), 1, "overlap goes to the first mapping");
+    assert_eq!(bus.read(0xD0FF), 1);
+    assert_eq!(bus.read(0xE005), 2, "second mapping of the same device");
+    assert_eq!(bus.read(0xD100), 0, "RAM outside the mappings");
+
+    bus.write(0xE003, 0x55);
+    assert_eq!(bus.mem.0[0xE003], 0, "device writes don't reach RAM");
+    bus.write(0xD100, 0x66);
+    assert_eq!(bus.mem.0[0xD100], 0x66);
+}
+
+#[test]
+fn mirrored_relative_address() {
+    let mapping = Mapping::new(0xA000..=0xBFFF).mirrored(2);
+    assert_eq!(mapping.relative(0xA000), 0);
+    assert_eq!(mapping.relative(0xA003), 1);
+    assert_eq!(mapping.relative(0xBFFE), 0);
+    assert_eq!(Mapping::new(0xA000..=0xBFFF).relative(0xA123), 0x123);
+
+    let mut bus = Bus::new(Memory::new());
+    let id = bus.attach(Box::new(Latch {
+        name: "acia",
+        tag: 0,
+        echo: true,
+    }));
+    bus.map(id, mapping);
+    assert_eq!(bus.read(0xA000), 0);
+    assert_eq!(bus.read(0xB7FF), 1);
+    assert_eq!(bus.peek(0xBFFE), 0);
+}
+
+/// A bus with latches of these names, mapped one after another from $D000.
+fn bus_with(names: &[&'static str]) -> Bus {
+    let mut bus = Bus::new(Memory::new());
+    for (i, name) in names.iter().enumerate() {
+        let id = bus.attach(latch(name, i as u8));
+        let start = 0xD000 + i as u16 * 0x10;
+        bus.map(id, Mapping::new(start..=start + 0xF));
+    }
+    bus
+}
+
+#[test]
+fn load_state_needs_the_same_devices() {
+    let mut w = StateWriter::new();
+    bus_with(&["serial", "timer"]).save_state(&mut w);
+    let state = w.into_bytes();
+
+    let load = |bus: &mut Bus| bus.load_state(&mut StateReader::new(&state).unwrap());
+
+    assert!(load(&mut bus_with(&["serial", "timer"])).is_ok());
+    for names in [
+        &["serial"][..],
+        &["serial", "timer", "display"],
+        &["serial", "clock"],
+        &["timer", "serial"],
+    ] {
+        let err = load(&mut bus_with(names)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", names);
+    }
+}
+
+#[test]
+fn display_mapped_over_more_than_its_buffer() {
+    let mut bus = Bus::new(Memory::new());
+    let id = bus.attach(Box::new(Display::new()));
+    bus.map(id, Mapping::new(0x0200..=0x09FF));
+
+    bus.write(0x0205, 7);
+    assert_eq!(bus.read(0x0605), 7, "pixels repeat every $400 bytes");
+    assert_eq!(bus.peek(0x09FF), bus.peek(0x05FF));
+}
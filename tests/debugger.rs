@@ -0,0 +1,75 @@
+//! Several debuggers running in one process don't interfere with each other.
+
+use std::{thread, time::Duration};
+
+use nes::{
+    cpu::HaltReason,
+    debugger::{Debugger, RunState},
+    machine::Machine,
+};
+
+/// Count in $10 forever.
+#[rustfmt::skip]
+const COUNTER: [u8; 5] = [
+    0xE6, 0x10,       // 0200 INC $10
+    0x4C, 0x00, 0x02, // 0202 JMP $0200
+];
+
+fn debugger() -> Debugger {
+    let machine = Machine::builder()
+        .serial_at(None)
+        .image(&COUNTER, 0x0200)
+        .image(&[0x00, 0x02], 0xFFFC)
+        .build();
+    let mut d = Debugger::new(machine);
+    d.max_speed = true;
+    d
+}
+
+fn counter(d: &Debugger) -> u8 {
+    d.machine.lock().cpu.mem.mem.0[0x10]
+}
+
+#[test]
+fn independent_debuggers() {
+    let mut breakpoint = debugger();
+    breakpoint.breakpoints.push(0x0202);
+    let mut limited = debugger();
+    limited.cycle_limit = Some(10_000);
+    let mut free = debugger();
+
+    free.run();
+    breakpoint.run();
+    limited.run();
+
+    assert_eq!(breakpoint.wait(), Some(HaltReason::Breakpoint(0x0202)));
+    assert_eq!(counter(&breakpoint), 1);
+    assert!(matches!(limited.wait(), Some(HaltReason::CycleLimit(_))));
+    assert!(limited.machine.lock().cpu.cycles >= 10_000);
+
+    // Still going after the others halted
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(free.state(), RunState::Running);
+    free.pause();
+    assert_eq!(free.state(), RunState::Paused);
+    assert_eq!(free.halt_reason(), None);
+    assert!(free.machine.lock().cpu.cycles > 0);
+
+    // Pausing one doesn't touch the others
+    assert_eq!(
+        breakpoint.state(),
+        RunState::Halted(HaltReason::Breakpoint(0x0202))
+    );
+    assert!(matches!(
+        limited.state(),
+        RunState::Halted(HaltReason::CycleLimit(_))
+    ));
+
+    // And a halted one can carry on while another is paused
+    breakpoint.breakpoints.clear();
+    breakpoint.cycle_limit = Some(breakpoint.machine.lock().cpu.cycles + 1_000);
+    breakpoint.run();
+    assert!(matches!(breakpoint.wait(), Some(HaltReason::CycleLimit(_))));
+    assert!(counter(&breakpoint) > 1);
+    assert_eq!(free.state(), RunState::Paused);
+}
@@ -0,0 +1,173 @@
+//! Devices attached through the machine builder see the CPU's clock.
+
+use std::io;
+
+use nes::{
+    cpu::HaltReason,
+    device::{Device, Mapping},
+    io::IO,
+    machine::Machine,
+    state::{SaveState, StateReader, StateWriter},
+};
+
+/// Raises IRQ every `period` cycles. Reading it acknowledges the interrupt.
+struct Timer {
+    period: u64,
+    elapsed: u64,
+    pending: bool,
+}
+
+impl IO for Timer {
+    fn read(&mut self, _addr: u16) -> u8 {
+        let pending = self.pending;
+        self.pending = false;
+        pending as u8
+    }
+
+    fn peek(&self, _addr: u16) -> u8 {
+        self.pending as u8
+    }
+
+    fn write(&mut self, _addr: u16, _data: u8) {}
+
+    fn tick(&mut self, cycles: u32) {
+        self.elapsed += cycles as u64;
+        if self.elapsed >= self.period {
+            self.elapsed -= self.period;
+            self.pending = true;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.pending
+    }
+}
+
+impl SaveState for Timer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u64(self.elapsed);
+        w.bool(self.pending);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.elapsed = r.u64()?;
+        self.pending = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+}
+
+/// Count timer interrupts over 100,000 cycles.
+fn count_interrupts(cycle_accurate: bool, block_cache: bool) -> u8 {
+    #[rustfmt::skip]
+    let main = [
+        0x58,             // 0200 CLI
+        0x4C, 0x01, 0x02, // 0201 JMP $0201
+    ];
+    #[rustfmt::skip]
+    let handler = [
+        0xAD, 0x00, 0xD0, // 0300 LDA $D000   acknowledge
+        0xE6, 0x10,       // 0303 INC $10
+        0x40,             // 0305 RTI
+    ];
+
+    let timer = Timer {
+        period: 1000,
+        elapsed: 0,
+        pending: false,
+    };
+    let mut machine = Machine::builder()
+        .serial_at(None)
+        .device(timer, vec![Mapping::new(0xD000..=0xD000)])
+        .cycle_accurate(cycle_accurate)
+        .block_cache(block_cache)
+        .image(&main, 0x0200)
+        .image(&handler, 0x0300)
+        .image(&[0x00, 0x03], 0xFFFE)
+        .build();
+    machine.cpu.pc = 0x0200;
+
+    machine.run_for_cycles(100_000);
+    machine.cpu.mem.mem.0[0x10]
+}
+
+#[test]
+fn timer_interrupts() {
+    let count = count_interrupts(false, false);
+    assert!((99..=100).contains(&count), "{} interrupts", count);
+
+    assert_eq!(count_interrupts(true, false), count, "cycle accurate");
+    assert_eq!(count_interrupts(false, true), count, "block cache");
+}
+
+/// Run `main` at $0200 with the timer until it halts on its own or has
+/// taken three interrupts.
+fn idle(main: &[u8]) -> (HaltReason, u8) {
+    #[rustfmt::skip]
+    let handler = [
+        0xAD, 0x00, 0xD0, // 0300 LDA $D000   acknowledge
+        0xE6, 0x10,       // 0303 INC $10
+        0x40,             // 0305 RTI
+    ];
+
+    let timer = Timer {
+        period: 1000,
+        elapsed: 0,
+        pending: false,
+    };
+    let mut machine = Machine::builder()
+        .serial_at(None)
+        .device(timer, vec![Mapping::new(0xD000..=0xD000)])
+        .image(main, 0x0200)
+        .image(&handler, 0x0300)
+        .image(&[0x00, 0x03], 0xFFFE)
+        .build();
+    machine.cpu.pc = 0x0200;
+
+    let reason = machine.run(|cpu| match cpu.halt_reason() {
+        None if cpu.mem.peek(0x10) == 3 => Some(HaltReason::Breakpoint(cpu.pc)),
+        None if cpu.cycles >= 100_000 => Some(HaltReason::CycleLimit(cpu.cycles)),
+        reason => reason,
+    });
+    (reason, machine.cpu.mem.peek(0x10))
+}
+
+#[test]
+fn idle_loop_waits_for_interrupts() {
+    // CLI; JMP *
+    let (reason, count) = idle(&[0x58, 0x4C, 0x01, 0x02]);
+    assert!(matches!(reason, HaltReason::Breakpoint(_)), "{}", reason);
+    assert_eq!(count, 3);
+
+    // SEI; JMP *
+    let (reason, count) = idle(&[0x78, 0x4C, 0x01, 0x02]);
+    assert_eq!(reason, HaltReason::InfiniteLoop(0x0201), "stuck with IRQs masked");
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn peek_has_no_side_effects() {
+    let timer = Timer {
+        period: 10,
+        elapsed: 0,
+        pending: false,
+    };
+    let mut machine = Machine::builder()
+        .serial_at(None)
+        .device(timer, vec![Mapping::new(0xD000..=0xD0FF).mirrored(1)])
+        .build();
+    machine.cpu.mem.tick(10);
+
+    assert_eq!(machine.cpu.mem.peek(0xD000), 1);
+    assert_eq!(machine.cpu.mem.peek_all()[0xD0FF], 1, "mirror");
+    assert_eq!(machine.cpu.trace_line(), machine.cpu.trace_line());
+    assert!(machine.cpu.mem.irq(), "still pending after peeking");
+
+    assert_eq!(machine.cpu.mem.read(0xD000), 1);
+    assert_eq!(machine.cpu.mem.peek(0xD000), 0, "acknowledged by reading");
+}
@@ -0,0 +1,8 @@
+#!/bin/sh
+# Download Klaus Dormann's 6502 functional test, which is GPL-3.0 licensed
+# and so isn't checked in. See README.md.
+set -e
+
+cd "$(dirname "$0")"
+curl -fsSL -o 6502_functional_test.bin \
+    https://raw.githubusercontent.com/Klaus2m5/6502_65C02_functional_tests/master/bin_files/6502_functional_test.bin
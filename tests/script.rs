@@ -0,0 +1,156 @@
+//! Expect-style scripts driving a 6551 ACIA.
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use nes::{
+    io::IO,
+    serial::{
+        script::{Clock, Outcome, Script, ScriptBackend, ScriptStatus, Step},
+        Serial,
+    },
+};
+
+const DATA: u16 = 0;
+const STATUS: u16 = 1;
+const COMMAND: u16 = 2;
+const CONTROL: u16 = 3;
+
+/// Cycles per character at 19200 baud, 8N1, with a 2 MHz CPU
+const CHAR_CYCLES: u32 = 1042;
+
+/// A clock that only moves when told to.
+#[derive(Clone)]
+struct ManualClock(Arc<Mutex<Instant>>);
+
+impl ManualClock {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+
+    fn clock(&self) -> Clock {
+        let now = self.0.clone();
+        Arc::new(move || *now.lock().unwrap())
+    }
+}
+
+fn open(script: &str, clock: &ManualClock) -> (Serial, ScriptStatus) {
+    let script = Script::parse(script).unwrap();
+    let backend = ScriptBackend::with_clock(script, io::sink(), clock.clock());
+    let status = backend.status();
+    let mut acia = Serial::new(Box::new(backend));
+    acia.write(CONTROL, 0x1F); // 19200 baud, 8 bits, 1 stop bit
+    acia.write(COMMAND, 0x0B); // DTR, no interrupts
+    (acia, status)
+}
+
+/// Transmit `text` from the machine.
+fn transmit(acia: &mut Serial, text: &[u8]) {
+    for &byte in text {
+        acia.write(DATA, byte);
+        acia.tick(CHAR_CYCLES + 1);
+    }
+}
+
+/// Everything the script types over `chars` character times.
+fn receive(acia: &mut Serial, chars: usize) -> Vec<u8> {
+    let mut received = vec![];
+    for _ in 0..chars * 2 {
+        acia.tick(CHAR_CYCLES / 2);
+        if acia.read(STATUS) & 0x08 != 0 {
+            received.push(acia.read(DATA));
+        }
+    }
+    received
+}
+
+#[test]
+fn parse() {
+    let script = Script::parse(
+        "# comment\n\
+         timeout 2.5\n\
+         expect \"Cold [C] or warm [W] start?\"\n\
+         send \"C\"\n\
+         rate 0\n\
+         sendline \"10 PRINT \\\"HI\\\"\\x07\"\n\
+         delay 0.1\n",
+    )
+    .unwrap();
+    assert_eq!(
+        script.steps,
+        vec![
+            Step::Timeout(Duration::from_millis(2500)),
+            Step::Expect(b"Cold [C] or warm [W] start?".to_vec()),
+            Step::Send(b"C".to_vec()),
+            Step::Rate(0.0),
+            Step::Send(b"10 PRINT \"HI\"\x07\r".to_vec()),
+            Step::Delay(Duration::from_millis(100)),
+        ]
+    );
+
+    let err = Script::parse("send \"A\"\nsned \"B\"").unwrap_err();
+    assert_eq!(err.to_string(), "line 2: unknown command \"sned\"");
+    assert!(Script::parse("expect OK").is_err(), "unquoted");
+    assert!(Script::parse("delay soon").is_err());
+}
+
+#[test]
+fn conversation() {
+    let clock = ManualClock::new();
+    let (mut acia, status) = open(
+        "rate 0\nexpect \"start?\"\nsend \"C\"\nexpect \"OK\"\n",
+        &clock,
+    );
+    assert_eq!(receive(&mut acia, 2), b"", "nothing sent before the prompt");
+
+    transmit(&mut acia, b"Cold [C] or warm [W] start?");
+    assert_eq!(receive(&mut acia, 2), b"C");
+    assert_eq!(status.expecting(), Some(b"OK".to_vec()));
+
+    transmit(&mut acia, b"\r\nOK");
+    assert_eq!(status.outcome(), Outcome::Passed);
+}
+
+#[test]
+fn paced() {
+    let clock = ManualClock::new();
+    let (mut acia, status) = open("rate 20\nsend \"AB\"\n", &clock);
+    assert_eq!(receive(&mut acia, 10), b"A");
+    clock.advance(Duration::from_millis(49));
+    assert_eq!(receive(&mut acia, 10), b"", "the next one is 50 ms away");
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(receive(&mut acia, 10), b"B");
+    assert_eq!(status.outcome(), Outcome::Passed);
+}
+
+#[test]
+fn unread_characters_overrun() {
+    let clock = ManualClock::new();
+    let (mut acia, _) = open("rate 0\nsend \"AB\"\n", &clock);
+    acia.tick(CHAR_CYCLES * 3);
+    assert_eq!(acia.read(STATUS) & 0x0C, 0x0C, "RDRF and overrun");
+    assert_eq!(acia.read(DATA), b'A', "B is lost");
+}
+
+#[test]
+fn timeout() {
+    let clock = ManualClock::new();
+    let (mut acia, status) = open("timeout 0.05\nexpect \"OK\"\n", &clock);
+    transmit(&mut acia, b"?SN ERROR");
+    clock.advance(Duration::from_millis(49));
+    assert_eq!(status.outcome(), Outcome::Running);
+
+    // Fails even with nothing polling the port
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(
+        status.outcome(),
+        Outcome::Failed("timed out waiting for \"OK\"".to_string())
+    );
+}
@@ -0,0 +1,236 @@
+//! 6551 ACIA timing, status and interrupts, and the serial backends.
+//!
+//! The behaviour tests talk to an in-memory host so they don't depend on
+//! how quickly the operating system moves bytes around.
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    net::TcpStream,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use nes::{
+    device::Device,
+    io::IO,
+    serial::{
+        backend::{Backend, FileBackend, TcpBackend, TtyBackend},
+        Serial,
+    },
+};
+use serialport::{SerialPort, TTYPort};
+
+const DATA: u16 = 0;
+const STATUS: u16 = 1;
+const COMMAND: u16 = 2;
+const CONTROL: u16 = 3;
+
+/// 19200 baud 8N1 at the default 2 MHz clock
+const CHAR_CYCLES: u32 = 1042;
+
+/// The other end of the serial line, held in memory.
+#[derive(Clone, Default)]
+struct Host {
+    /// Waiting to be received by the ACIA
+    input: Arc<Mutex<VecDeque<u8>>>,
+    /// Transmitted by the ACIA
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Host {
+    fn send(&self, bytes: &[u8]) {
+        self.input.lock().unwrap().extend(bytes);
+    }
+
+    fn received(&self) -> Vec<u8> {
+        self.output.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl Backend for Host {
+    fn read(&mut self) -> Option<u8> {
+        self.input.lock().unwrap().pop_front()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.output.lock().unwrap().push(byte);
+    }
+}
+
+fn open() -> (Host, Serial) {
+    let host = Host::default();
+    let serial = Serial::new(Box::new(host.clone()));
+    (host, serial)
+}
+
+/// Tick until `done` holds, giving a real connection up to a second.
+fn tick_until<F: FnMut(&mut Serial) -> bool>(acia: &mut Serial, mut done: F) {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while !done(acia) {
+        assert!(Instant::now() < deadline, "timed out");
+        acia.tick(CHAR_CYCLES);
+    }
+}
+
+#[test]
+fn transmit_takes_a_character_time() {
+    let (host, mut acia) = open();
+    acia.write(CONTROL, 0x1F); // 19200 baud, 8 bits, 1 stop bit
+    acia.write(COMMAND, 0x0B); // DTR, no interrupts, RTS low
+
+    acia.write(DATA, b'A');
+    assert_eq!(acia.read(STATUS) & 0x10, 0, "TDRE clear after write");
+
+    acia.tick(1);
+    assert_eq!(acia.read(STATUS) & 0x10, 0x10, "TDRE set once in the shift register");
+
+    acia.tick(CHAR_CYCLES - 10);
+    assert_eq!(host.received(), b"", "still shifting out");
+
+    acia.tick(10);
+    assert_eq!(host.received(), b"A");
+}
+
+#[test]
+fn character_time_follows_the_cpu_clock() {
+    let (host, mut acia) = open();
+    acia.set_cpu_clock(1_000_000);
+    acia.write(CONTROL, 0x1F);
+    acia.write(COMMAND, 0x0B);
+
+    acia.write(DATA, b'A');
+    acia.tick(1);
+    acia.tick(CHAR_CYCLES / 2 - 10);
+    assert_eq!(host.received(), b"", "still shifting out");
+
+    acia.tick(10);
+    assert_eq!(host.received(), b"A");
+}
+
+#[test]
+fn receive_interrupt_and_overrun() {
+    let (host, mut acia) = open();
+    acia.write(CONTROL, 0x1F);
+    acia.write(COMMAND, 0x09); // DTR, receive interrupts on
+
+    host.send(b"Z");
+    acia.tick(CHAR_CYCLES);
+    assert!(acia.irq());
+    assert_eq!(acia.read(STATUS) & 0x88, 0x88, "IRQ and RDRF");
+    assert!(!acia.irq(), "reading status clears IRQ");
+    assert_eq!(acia.read(DATA), b'Z');
+    assert_eq!(acia.read(STATUS) & 0x08, 0, "reading data clears RDRF");
+
+    host.send(b"12");
+    acia.tick(CHAR_CYCLES * 3);
+    assert_eq!(acia.read(STATUS) & 0x0C, 0x0C, "RDRF and overrun");
+    assert_eq!(acia.read(DATA), b'1', "the second character is lost");
+    assert_eq!(acia.read(STATUS) & 0x0C, 0);
+
+    // Programmed reset turns off DTR, and with it the receiver
+    acia.write(STATUS, 0);
+    assert_eq!(acia.read(COMMAND) & 0x1F, 0);
+    host.send(b"X");
+    acia.tick(CHAR_CYCLES * 2);
+    assert!(!acia.irq());
+    assert_eq!(acia.read(STATUS) & 0x08, 0);
+}
+
+#[test]
+fn parity_errors() {
+    let (host, mut acia) = open();
+    acia.write(CONTROL, 0x3F); // 19200 baud, 7 bits
+    acia.write(COMMAND, 0x6B); // even parity
+
+    // 'A' has an even number of ones, so its parity bit is clear
+    host.send(&[0x41]);
+    acia.tick(CHAR_CYCLES);
+    assert_eq!(acia.read(STATUS) & 0x01, 0);
+    assert_eq!(acia.read(DATA), 0x41);
+
+    host.send(&[0xC1]);
+    acia.tick(CHAR_CYCLES);
+    assert_eq!(acia.read(STATUS) & 0x01, 0x01, "parity error");
+    assert_eq!(acia.read(DATA), 0x41, "parity bit stripped");
+}
+
+#[test]
+fn tty_backend() {
+    let (mut host, device) = TTYPort::pair().unwrap();
+    host.set_timeout(Duration::from_secs(1)).unwrap();
+    let mut acia = Serial::new(Box::new(TtyBackend::open(&device.name().unwrap()).unwrap()));
+    acia.write(CONTROL, 0x1F);
+    acia.write(COMMAND, 0x0B);
+
+    host.write_all(b"P").unwrap();
+    tick_until(&mut acia, |acia| acia.read(STATUS) & 0x08 != 0);
+    assert_eq!(acia.read(DATA), b'P');
+
+    acia.write(DATA, b'Q');
+    acia.tick(CHAR_CYCLES + 1);
+    let mut buf = [0];
+    host.read_exact(&mut buf).unwrap();
+    assert_eq!(buf[0], b'Q');
+}
+
+#[test]
+fn tcp_backend() {
+    let backend = TcpBackend::bind("127.0.0.1:0").unwrap();
+    let addr = backend.local_addr().unwrap();
+    let mut acia = Serial::new(Box::new(backend));
+    acia.write(CONTROL, 0x1F);
+    acia.write(COMMAND, 0x0B);
+    acia.tick(CHAR_CYCLES);
+    assert_eq!(acia.read(STATUS) & 0x20, 0x20, "no carrier without a client");
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    client.write_all(b"H").unwrap();
+    tick_until(&mut acia, |acia| acia.read(STATUS) & 0x08 != 0);
+    assert_eq!(acia.read(STATUS) & 0x20, 0, "carrier");
+    assert_eq!(acia.read(DATA), b'H');
+
+    acia.write(DATA, b'i');
+    acia.tick(CHAR_CYCLES + 1);
+    let mut buf = [0];
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(buf[0], b'i');
+}
+
+#[test]
+fn file_backend() {
+    let (sender, receiver) = mpsc::channel();
+    let mut acia = Serial::new(Box::new(FileBackend::new(b"ok".to_vec(), Sink(sender))));
+    acia.write(CONTROL, 0x1F);
+    acia.write(COMMAND, 0x0B);
+
+    let mut input = vec![];
+    for _ in 0..8 {
+        acia.tick(CHAR_CYCLES / 2);
+        if acia.read(STATUS) & 0x08 != 0 {
+            input.push(acia.read(DATA));
+        }
+    }
+    assert_eq!(input, b"ok");
+
+    acia.write(DATA, b'!');
+    acia.tick(CHAR_CYCLES + 1);
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), b"!");
+}
+
+/// Sends everything written to it down a channel.
+struct Sink(mpsc::Sender<u8>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            let _ = self.0.send(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! Per-opcode conformance tests in the SingleStepTests JSON format
+//! (https://github.com/SingleStepTests/65x02).
+//!
+//! Each file is named after an opcode (e.g. `a9.json`) and holds a list of vectors
+//! with the initial and final registers and RAM, and the bus activity of every cycle.
+//! A small hand-written set is vendored in `tests/single_step`. To run the full
+//! suite, point `SINGLE_STEP_TESTS` at a checkout of the 65x02 repository and run
+//! the ignored tests.
+//!
+//! The B flag doesn't exist in the status register, so it is ignored when comparing P.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use nes::{
+    cpu::{AccessKind, BusAccess, Opcode, Status, Variant, CPU6502},
+    io::IO,
+};
+use serde::Deserialize;
+
+/// Flat 64K RAM, cleared between vectors by undoing only what was touched.
+struct FlatRam {
+    mem: Vec<u8>,
+    touched: Vec<u16>,
+}
+
+impl FlatRam {
+    fn new() -> Self {
+        Self {
+            mem: vec![0; 0x10000],
+            touched: vec![],
+        }
+    }
+
+    fn clear(&mut self) {
+        for addr in self.touched.drain(..) {
+            self.mem[addr as usize] = 0;
+        }
+    }
+}
+
+impl IO for FlatRam {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.mem[addr as usize]
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.mem[addr as usize]
+    }
+    fn write(&mut self, addr: u16, data: u8) {
+        self.touched.push(addr);
+        self.mem[addr as usize] = data;
+    }
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+#[derive(Deserialize)]
+struct Vector {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    /// Address, value and "read" or "write"
+    cycles: Vec<(u16, u8, String)>,
+}
+
+impl Vector {
+    fn bus_accesses(&self) -> Vec<BusAccess> {
+        self.cycles
+            .iter()
+            .map(|(addr, value, kind)| BusAccess {
+                addr: *addr,
+                value: *value,
+                kind: match kind.as_str() {
+                    "write" => AccessKind::Write,
+                    _ => AccessKind::Read,
+                },
+            })
+            .collect()
+    }
+}
+
+/// Run one vector, returning a description of every mismatch.
+fn run_vector(cpu: &mut CPU6502<FlatRam>, vector: &Vector) -> Vec<String> {
+    let initial = &vector.initial;
+    let expected = &vector.expected;
+    let expected_cycles = vector.bus_accesses();
+
+    cpu.mem.clear();
+    for &(addr, value) in &initial.ram {
+        cpu.mem.write(addr, value);
+    }
+    cpu.pc = initial.pc;
+    cpu.sp = initial.s;
+    cpu.a = initial.a;
+    cpu.x = initial.x;
+    cpu.y = initial.y;
+    cpu.p = Status::from_bits_truncate(initial.p) | Status::U;
+    cpu.cycles_left = 0;
+    cpu.waiting = false;
+    cpu.stopped = false;
+
+    let result = cpu.step_instruction();
+    for access in &result.accesses {
+        cpu.mem.touched.push(access.addr);
+    }
+
+    let mut errors = vec![];
+    let mut check = |name: &str, actual: u16, expected: u16| {
+        if actual != expected {
+            errors.push(format!("{} = ${:02X}, expected ${:02X}", name, actual, expected));
+        }
+    };
+
+    check("PC", cpu.pc, expected.pc);
+    check("S", cpu.sp as u16, expected.s as u16);
+    check("A", cpu.a as u16, expected.a as u16);
+    check("X", cpu.x as u16, expected.x as u16);
+    check("Y", cpu.y as u16, expected.y as u16);
+    check(
+        "P",
+        (cpu.p.bits() & !Status::B.bits()) as u16,
+        (expected.p & !Status::B.bits()) as u16,
+    );
+    for &(addr, value) in &expected.ram {
+        check(&format!("${:04X}", addr), cpu.mem.mem[addr as usize] as u16, value as u16);
+    }
+
+    if result.accesses != expected_cycles {
+        errors.push(format!(
+            "bus activity {:?}, expected {:?}",
+            result.accesses, expected_cycles
+        ));
+    }
+
+    errors
+}
+
+/// Run every opcode file found in `dir` and panic with a report of the failing opcodes.
+fn run_suite(dir: &Path, variant: Variant) {
+    let mut cpu = CPU6502::new(FlatRam::new());
+    cpu.variant = variant;
+    let instruction_set = cpu.instruction_set();
+
+    let mut files = 0;
+    let mut vectors = 0;
+    let mut failures = vec![];
+
+    for opcode in 0..=255u8 {
+        let instruction = instruction_set[opcode as usize];
+
+        // JAM is emulated as a halt, not as the bus activity of a locked-up CPU.
+        if matches!(instruction.0, Opcode::JAM) {
+            continue;
+        }
+
+        let path = dir.join(format!("{:02x}.json", opcode));
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        files += 1;
+
+        let tests: Vec<Vector> =
+            serde_json::from_str(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let mut failed = 0;
+        let mut first_failure = None;
+
+        for vector in &tests {
+            vectors += 1;
+            let errors = run_vector(&mut cpu, vector);
+            if !errors.is_empty() {
+                failed += 1;
+                first_failure.get_or_insert_with(|| {
+                    format!("\"{}\": {}", vector.name, errors.join(", "))
+                });
+            }
+        }
+
+        if let Some(first_failure) = first_failure {
+            failures.push(format!(
+                "{:02X} {:?} {:?}: {} of {} failed, first {}",
+                opcode,
+                instruction.0,
+                instruction.1,
+                failed,
+                tests.len(),
+                first_failure
+            ));
+        }
+    }
+
+    assert!(files > 0, "no test files in {}", dir.display());
+    assert!(
+        failures.is_empty(),
+        "{} of {} opcodes failed ({} vectors run):\n{}",
+        failures.len(),
+        files,
+        vectors,
+        failures.join("\n")
+    );
+}
+
+fn vendored(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("single_step")
+        .join(name)
+}
+
+#[test]
+fn single_step_6502() {
+    run_suite(&vendored("6502"), Variant::NMOS6502);
+}
+
+#[test]
+fn single_step_65c02() {
+    run_suite(&vendored("wdc65c02"), Variant::WDC65C02);
+}
+
+#[test]
+#[ignore = "set SINGLE_STEP_TESTS to a checkout of the 65x02 repository"]
+fn single_step_full_suite() {
+    let root = env::var_os("SINGLE_STEP_TESTS")
+        .map(PathBuf::from)
+        .expect("SINGLE_STEP_TESTS not set");
+
+    run_suite(&root.join("6502").join("v1"), Variant::NMOS6502);
+    run_suite(&root.join("wdc65c02").join("v1"), Variant::WDC65C02);
+}
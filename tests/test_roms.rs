@@ -0,0 +1,98 @@
+//! Klaus Dormann's 6502 functional test and Bruce Clark's decimal mode test.
+//!
+//! Both suites signal the end of a run by trapping in a branch or jump to itself,
+//! which the CPU reports as `HaltReason::InfiniteLoop`. See `tests/roms/README.md`.
+
+use std::{fs, path::Path};
+
+use nes::{
+    cpu::{HaltReason, Variant, CPU6502},
+    mem::Memory,
+};
+
+const MAX_CYCLES: u64 = 200_000_000;
+
+fn load_rom(name: &str) -> Option<Vec<u8>> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("roms")
+        .join(name);
+    fs::read(path).ok()
+}
+
+fn new_cpu(rom: &[u8], offset: u16, start: u16, variant: Variant) -> CPU6502<Memory> {
+    let mut mem = Memory::new();
+    mem.load(rom, offset);
+
+    let mut cpu = CPU6502::new(mem);
+    cpu.variant = variant;
+    cpu.reset();
+    cpu.pc = start;
+    cpu
+}
+
+/// Run until the program traps, returning the address of the trap.
+fn run_to_trap(cpu: &mut CPU6502<Memory>) -> u16 {
+    let reason = cpu.run_until(|cpu| match cpu.halt_reason() {
+        None if cpu.cycles >= MAX_CYCLES => Some(HaltReason::CycleLimit(cpu.cycles)),
+        reason => reason,
+    });
+
+    match reason {
+        HaltReason::InfiniteLoop(addr) => addr,
+        HaltReason::CycleLimit(cycles) => {
+            panic!("no trap after {} cycles, PC = ${:04X}", cycles, cpu.pc)
+        }
+        reason => panic!("unexpected halt: {}", reason),
+    }
+}
+
+#[test]
+fn functional_test() {
+    const START: u16 = 0x0400;
+    const SUCCESS: u16 = 0x3469;
+    const TEST_CASE: usize = 0x0200;
+
+    let rom = load_rom("6502_functional_test.bin")
+        .expect("tests/roms/6502_functional_test.bin not found, run tests/roms/fetch.sh");
+
+    let mut cpu = new_cpu(&rom, 0x0000, START, Variant::NMOS6502);
+    let trap = run_to_trap(&mut cpu);
+
+    assert_eq!(
+        trap, SUCCESS,
+        "failure trap at ${:04X} in test ${:02X} after {} cycles",
+        trap, cpu.mem.0[TEST_CASE], cpu.cycles
+    );
+}
+
+fn decimal_test(name: &str, variant: Variant) {
+    const START: u16 = 0x0200;
+    const DONE: u16 = 0x0203;
+    const ERROR: usize = 0x04;
+    const N1: usize = 0x07;
+    const N2: usize = 0x0A;
+
+    let rom = load_rom(name).expect("decimal test binary is vendored");
+
+    let mut cpu = new_cpu(&rom, START, START, variant);
+    let trap = run_to_trap(&mut cpu);
+    assert_eq!(trap, DONE, "unexpected trap at ${:04X}", trap);
+
+    let mem = &cpu.mem.0;
+    assert_eq!(
+        mem[ERROR], 0,
+        "decimal mode mismatch: N1 = ${:02X}, N2 = ${:02X}, carry in = {}",
+        mem[N1], mem[N2], cpu.y
+    );
+}
+
+#[test]
+fn decimal_test_6502() {
+    decimal_test("6502_decimal_test.bin", Variant::NMOS6502);
+}
+
+#[test]
+fn decimal_test_65c02() {
+    decimal_test("65c02_decimal_test.bin", Variant::WDC65C02);
+}